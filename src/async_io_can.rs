@@ -0,0 +1,184 @@
+//! Runtime independent async support based on async-io (feature `async-io`), e.g. for smol and async-std.
+
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+use async_io::Async;
+use chrono::Duration;
+use futures_core::Stream;
+use futures_sink::Sink;
+
+use crate::{Can, CanGroup, CanGroupError, Frame, Msg, TimestampedFrame};
+
+/// CAN socket for async-io based runtimes.
+///
+/// Receives frames as `Stream` and sends them as `Sink`. Error frames are not converted
+/// into `Frame`s, receive them with `AsyncIoCan::recv_msg`.
+pub struct AsyncIoCan {
+  inner: Async<Can>,
+  msg: Box<Msg>,
+  pending: Option<Frame>,
+}
+impl AsyncIoCan {
+  /// Switches the socket to non-blocking mode and registers it with the reactor.
+  pub fn new(can: Can) -> io::Result<AsyncIoCan> {
+    Ok(AsyncIoCan { inner: Async::new(can)?, msg: Msg::new(), pending: None })
+  }
+  /// Open the CAN device with the netdev name, see `Can::open`.
+  pub fn open(ifname: &str) -> io::Result<AsyncIoCan> {
+    AsyncIoCan::new(Can::open(ifname)?)
+  }
+  /// Get the socket, e.g. to change its filters.
+  pub fn get_ref(&self) -> &Can {
+    self.inner.get_ref()
+  }
+  /// Deregisters the socket from the reactor and returns it. It stays non-blocking.
+  pub fn into_inner(self) -> io::Result<Can> {
+    self.inner.into_inner()
+  }
+  /// Receives the next frame.
+  pub async fn recv(&mut self) -> io::Result<TimestampedFrame> {
+    loop {
+      let msg = self.recv_msg().await?;
+      if let Some(frame) = TimestampedFrame::from_msg(msg) {
+        return Ok(frame);
+      }
+    }
+  }
+  /// Receives the next message, including error frames.
+  pub async fn recv_msg(&mut self) -> io::Result<&Msg> {
+    let msg = &mut self.msg;
+    self.inner.read_with(|can| can.recv(msg)).await?;
+    Ok(&self.msg)
+  }
+  /// Sends the frame.
+  pub async fn send(&self, frame: &Frame) -> io::Result<()> {
+    self.inner.write_with(|can| can.send_frame(frame)).await
+  }
+  fn poll_send_pending(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+    while let Some(frame) = &self.pending {
+      match self.inner.get_ref().send_frame(frame) {
+        Err(err) if err.kind() == io::ErrorKind::WouldBlock => ready!(self.inner.poll_writable(cx))?,
+        result => {
+          self.pending = None;
+          result?;
+        }
+      }
+    }
+    Poll::Ready(Ok(()))
+  }
+}
+impl Stream for AsyncIoCan {
+  type Item = io::Result<TimestampedFrame>;
+  fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+    let this = self.get_mut();
+    loop {
+      match this.inner.get_ref().recv(&mut this.msg) {
+        Ok(()) => {
+          if let Some(frame) = TimestampedFrame::from_msg(&this.msg) {
+            return Poll::Ready(Some(Ok(frame)));
+          }
+        }
+        Err(err) if err.kind() == io::ErrorKind::WouldBlock => ready!(this.inner.poll_readable(cx))?,
+        Err(err) => return Poll::Ready(Some(Err(err))),
+      }
+    }
+  }
+}
+impl Sink<Frame> for AsyncIoCan {
+  type Error = io::Error;
+  fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+    self.get_mut().poll_send_pending(cx)
+  }
+  fn start_send(self: Pin<&mut Self>, frame: Frame) -> io::Result<()> {
+    self.get_mut().pending = Some(frame);
+    Ok(())
+  }
+  fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+    self.get_mut().poll_send_pending(cx)
+  }
+  fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+    self.get_mut().poll_send_pending(cx)
+  }
+}
+
+/// `CanGroup` for async-io based runtimes. The epoll fd of the group is registered with
+/// the reactor, so waiting for the members does not block the thread.
+pub struct AsyncIoCanGroup<T> {
+  inner: Async<CanGroup<T>>,
+}
+impl<T> AsyncIoCanGroup<T> {
+  /// Registers the group with the reactor.
+  pub fn new(group: CanGroup<T>) -> io::Result<AsyncIoCanGroup<T>> {
+    Ok(AsyncIoCanGroup { inner: Async::new(group)? })
+  }
+  /// Get the group.
+  pub fn get_ref(&self) -> &CanGroup<T> {
+    self.inner.get_ref()
+  }
+  /// Deregisters the group from the reactor and returns it.
+  pub fn into_inner(self) -> io::Result<CanGroup<T>> {
+    self.inner.into_inner()
+  }
+  /// Waits until at least one member received a frame and calls the callback like `CanGroup::next`.
+  pub async fn next<F: FnMut(&Msg, &mut T)>(&mut self, mut on_recv: F) -> Result<(), CanGroupError> {
+    loop {
+      self.inner.readable().await?;
+      // CanGroup::next neither closes nor replaces the epoll fd
+      if unsafe { self.inner.get_mut() }.next(Duration::zero(), &mut on_recv)? {
+        return Ok(());
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{socket_pair, CanFrame, Id};
+  use std::future::poll_fn;
+  #[test]
+  fn is_send() {
+    fn assert_send<T: Send>(_: T) {}
+    // smol::spawn and async_std::task::spawn require the sockets and their futures to be Send
+    let _ = |mut can: AsyncIoCan, frame: Frame| assert_send(async move {
+      can.send(&frame).await?;
+      can.recv().await
+    });
+    let _ = |mut group: AsyncIoCanGroup<u32>| assert_send(async move {
+      let mut received = 0;
+      group.next(|_, count| { *count += 1; received += 1; }).await
+    });
+  }
+  #[test]
+  fn stream_and_sink() {
+    async_io::block_on(async {
+      let (tx, rx) = socket_pair();
+      let mut tx = AsyncIoCan::new(tx).unwrap();
+      let mut rx = AsyncIoCan::new(rx).unwrap();
+      let frame = Frame::Can(CanFrame::new(Id::Standard(0x123), &[1, 2, 3]).unwrap());
+      tx.send(&frame).await.unwrap();
+      assert_eq!(rx.recv().await.unwrap().frame, frame);
+      let mut sink = Pin::new(&mut tx);
+      poll_fn(|cx| sink.as_mut().poll_ready(cx)).await.unwrap();
+      sink.as_mut().start_send(frame.clone()).unwrap();
+      poll_fn(|cx| sink.as_mut().poll_flush(cx)).await.unwrap();
+      let received = poll_fn(|cx| Pin::new(&mut rx).poll_next(cx)).await.unwrap().unwrap();
+      assert_eq!(received.frame, frame);
+    });
+  }
+  #[test]
+  fn group() {
+    async_io::block_on(async {
+      let (tx, rx) = socket_pair();
+      let mut group = CanGroup::new();
+      group.add(rx, 1000).unwrap();
+      let mut group = AsyncIoCanGroup::new(group).unwrap();
+      tx.send(0x12, &[]).unwrap();
+      let mut received = 0;
+      group.next(|msg, user_data| received = msg.can_id() + *user_data).await.unwrap();
+      assert_eq!(received, 0x12 + 1000);
+    });
+  }
+}
@@ -0,0 +1,153 @@
+//! Tokio support (feature `tokio`).
+
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+use futures_core::Stream;
+use futures_sink::Sink;
+use tokio::io::unix::AsyncFd;
+
+use crate::{Can, Frame, Msg, TimestampedFrame};
+
+/// CAN socket for the tokio runtime.
+///
+/// Receives frames as `Stream` and sends them as `Sink`. Error frames are not converted
+/// into `Frame`s, receive them with `AsyncCan::recv_msg`.
+pub struct AsyncCan {
+  inner: AsyncFd<Can>,
+  msg: Box<Msg>,
+  pending: Option<Frame>,
+}
+impl AsyncCan {
+  /// Switches the socket to non-blocking mode and registers it with the runtime.
+  /// Panics if called outside of a tokio runtime.
+  pub fn new(can: Can) -> io::Result<AsyncCan> {
+    can.set_nonblocking(true)?;
+    // Can owns its fd and only closes it when dropped, i.e. after the AsyncFd deregistered it
+    let inner = unsafe { AsyncFd::register(can)? };
+    Ok(AsyncCan { inner, msg: Msg::new(), pending: None })
+  }
+  /// Open the CAN device with the netdev name, see `Can::open`.
+  pub fn open(ifname: &str) -> io::Result<AsyncCan> {
+    AsyncCan::new(Can::open(ifname)?)
+  }
+  /// Get the socket, e.g. to change its filters.
+  pub fn get_ref(&self) -> &Can {
+    self.inner.get_ref()
+  }
+  /// Deregisters the socket from the runtime and returns it. It stays non-blocking.
+  pub fn into_inner(self) -> Can {
+    self.inner.into_inner()
+  }
+  /// Receives the next frame.
+  pub async fn recv(&mut self) -> io::Result<TimestampedFrame> {
+    loop {
+      let msg = self.recv_msg().await?;
+      if let Some(frame) = TimestampedFrame::from_msg(msg) {
+        return Ok(frame);
+      }
+    }
+  }
+  /// Receives the next message, including error frames.
+  pub async fn recv_msg(&mut self) -> io::Result<&Msg> {
+    loop {
+      let mut guard = self.inner.readable().await?;
+      match guard.try_io(|inner| inner.get_ref().recv(&mut self.msg)) {
+        Ok(result) => {
+          result?;
+          return Ok(&self.msg);
+        }
+        Err(_would_block) => continue,
+      }
+    }
+  }
+  /// Sends the frame.
+  pub async fn send(&self, frame: &Frame) -> io::Result<()> {
+    loop {
+      let mut guard = self.inner.writable().await?;
+      match guard.try_io(|inner| inner.get_ref().send_frame(frame)) {
+        Ok(result) => return result,
+        Err(_would_block) => continue,
+      }
+    }
+  }
+  fn poll_send_pending(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+    while let Some(frame) = &self.pending {
+      let mut guard = ready!(self.inner.poll_write_ready(cx))?;
+      match guard.try_io(|inner| inner.get_ref().send_frame(frame)) {
+        Ok(result) => {
+          self.pending = None;
+          result?;
+        }
+        Err(_would_block) => continue,
+      }
+    }
+    Poll::Ready(Ok(()))
+  }
+}
+impl Stream for AsyncCan {
+  type Item = io::Result<TimestampedFrame>;
+  fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+    let this = self.get_mut();
+    loop {
+      let mut guard = ready!(this.inner.poll_read_ready(cx))?;
+      match guard.try_io(|inner| inner.get_ref().recv(&mut this.msg)) {
+        Ok(Ok(())) => {
+          if let Some(frame) = TimestampedFrame::from_msg(&this.msg) {
+            return Poll::Ready(Some(Ok(frame)));
+          }
+        }
+        Ok(Err(err)) => return Poll::Ready(Some(Err(err))),
+        Err(_would_block) => continue,
+      }
+    }
+  }
+}
+impl Sink<Frame> for AsyncCan {
+  type Error = io::Error;
+  fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+    self.get_mut().poll_send_pending(cx)
+  }
+  fn start_send(self: Pin<&mut Self>, frame: Frame) -> io::Result<()> {
+    self.get_mut().pending = Some(frame);
+    Ok(())
+  }
+  fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+    self.get_mut().poll_send_pending(cx)
+  }
+  fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+    self.get_mut().poll_send_pending(cx)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{socket_pair, CanFrame, Id};
+  use std::future::poll_fn;
+  #[test]
+  fn is_send() {
+    fn assert_send<T: Send>(_: T) {}
+    // tokio::spawn on the multi-threaded runtime requires the socket and its futures to be Send
+    let _ = |mut can: AsyncCan, frame: Frame| assert_send(async move {
+      can.send(&frame).await?;
+      can.recv().await
+    });
+  }
+  #[tokio::test]
+  async fn stream_and_sink() {
+    let (tx, rx) = socket_pair();
+    let mut tx = AsyncCan::new(tx).unwrap();
+    let mut rx = AsyncCan::new(rx).unwrap();
+    let frame = Frame::Can(CanFrame::new(Id::Standard(0x123), &[1, 2, 3]).unwrap());
+    tx.send(&frame).await.unwrap();
+    assert_eq!(rx.recv().await.unwrap().frame, frame);
+    let mut sink = Pin::new(&mut tx);
+    poll_fn(|cx| sink.as_mut().poll_ready(cx)).await.unwrap();
+    sink.as_mut().start_send(frame.clone()).unwrap();
+    poll_fn(|cx| sink.as_mut().poll_flush(cx)).await.unwrap();
+    let received = poll_fn(|cx| Pin::new(&mut rx).poll_next(cx)).await.unwrap().unwrap();
+    assert_eq!(received.frame, frame);
+  }
+}
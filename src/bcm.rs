@@ -0,0 +1,447 @@
+//! Broadcast manager (`CAN_BCM`) sockets.
+//!
+//! The broadcast manager sends cyclic messages from within the kernel, so the cycle times
+//! are not affected by the scheduling of the application. On the receive side it filters
+//! frames by content and watches the cycle times of received messages.
+
+use std::io;
+use std::mem;
+use std::os::raw::{c_long, c_void};
+
+use chrono::Duration;
+
+use crate::frame::RawFrame;
+use crate::socket_fd::SocketFd;
+use crate::{CanFdFrame, CanFrame, Frame, Id, Msg, AF_CAN};
+
+// Opcodes and flags stolen from linux/can/bcm.h
+const TX_SETUP: u32 = 1;
+const TX_DELETE: u32 = 2;
+const TX_READ: u32 = 3;
+const TX_STATUS: u32 = 8;
+const RX_SETUP: u32 = 5;
+const RX_DELETE: u32 = 6;
+const TX_EXPIRED: u32 = 9;
+const RX_TIMEOUT: u32 = 11;
+const RX_CHANGED: u32 = 12;
+
+const SETTIMER: u32 = 0x0001;
+const STARTTIMER: u32 = 0x0002;
+const TX_COUNTEVT: u32 = 0x0004;
+const TX_ANNOUNCE: u32 = 0x0008;
+const RX_FILTER_ID: u32 = 0x0020;
+const RX_CHECK_DLC: u32 = 0x0040;
+const RX_ANNOUNCE_RESUME: u32 = 0x0100;
+pub(crate) const CAN_FD_FRAME: u32 = 0x0800;
+
+// the kernel refuses more frames per job
+const MAX_NFRAMES: usize = 256;
+
+/// `struct bcm_timeval`
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub(crate) struct BcmTimeval {
+  tv_sec: c_long,
+  tv_usec: c_long,
+}
+impl BcmTimeval {
+  fn from_duration(d: Duration) -> BcmTimeval {
+    let us = d.num_microseconds().unwrap_or(i64::MAX).max(0);
+    BcmTimeval { tv_sec: (us / 1_000_000) as c_long, tv_usec: (us % 1_000_000) as c_long }
+  }
+  // c_long is narrower than i64 on 32 bit targets
+  #[allow(clippy::unnecessary_cast)]
+  fn to_duration(self) -> Duration {
+    Duration::seconds(self.tv_sec as i64) + Duration::microseconds(self.tv_usec as i64)
+  }
+}
+
+/// `struct bcm_msg_head` without the trailing frames. The data of the frames is 8 byte aligned,
+/// which pads the head to a multiple of 8 bytes on 32 bit targets, too.
+#[repr(C, align(8))]
+#[derive(Clone, Copy, Default)]
+pub(crate) struct BcmMsgHead {
+  pub opcode: u32,
+  pub flags: u32,
+  pub count: u32,
+  pub ival1: BcmTimeval,
+  pub ival2: BcmTimeval,
+  pub can_id: u32,
+  pub nframes: u32,
+}
+pub(crate) const HEAD_SIZE: usize = mem::size_of::<BcmMsgHead>();
+
+/// Cyclic transmission job of the broadcast manager.
+///
+/// The kernel first sends `count` frames with the interval `ival1`, then continues with
+/// the interval `ival2` until the job is deleted. If more than one frame is given,
+/// they are sent in turn (multiplexed).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TxJob {
+  /// frames to send, all classic CAN or all CAN FD frames
+  pub frames: Vec<Frame>,
+  /// number of frames sent with `ival1`
+  pub count: u32,
+  /// interval of the initial burst
+  pub ival1: Duration,
+  /// interval after the initial burst, zero stops the transmission after the burst
+  pub ival2: Duration,
+  /// report `BcmEvent::TxExpired` when the initial burst finished
+  pub notify_expired: bool,
+  /// send the first frame immediately
+  pub announce: bool,
+}
+impl TxJob {
+  /// Creates a job which sends `frames` with the constant `interval`.
+  pub fn cyclic(frames: Vec<Frame>, interval: Duration) -> TxJob {
+    TxJob {
+      frames,
+      count: 0,
+      ival1: Duration::zero(),
+      ival2: interval,
+      notify_expired: false,
+      announce: true,
+    }
+  }
+}
+
+/// Receive job of the broadcast manager.
+///
+/// The kernel reports a received frame with the identifier only if the bits selected by
+/// `mask` changed since the last reported frame. The first frame is always reported.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RxJob {
+  /// identifier to watch
+  pub id: Id,
+  /// watch CAN FD frames instead of classic CAN frames
+  pub fd: bool,
+  /// data bits to compare, None reports every received frame
+  pub mask: Option<Vec<u8>>,
+  /// also report frames whose length changed
+  pub check_dlc: bool,
+  /// report `BcmEvent::RxTimeout` if no frame was received within this time
+  pub timeout: Option<Duration>,
+  /// report the first frame after a timeout even if its content did not change
+  pub announce_resume: bool,
+  /// minimum time between two reported frames, changes in between are merged
+  pub throttle: Option<Duration>,
+}
+impl RxJob {
+  /// Creates a job which reports every classic CAN frame with the identifier.
+  pub fn new(id: Id) -> RxJob {
+    RxJob {
+      id,
+      fd: false,
+      mask: None,
+      check_dlc: false,
+      timeout: None,
+      announce_resume: false,
+      throttle: None,
+    }
+  }
+  /// Only report frames if the data bits selected by `mask` changed.
+  pub fn with_mask(mut self, mask: &[u8]) -> RxJob {
+    self.mask = Some(mask.to_vec());
+    self
+  }
+  /// Report `BcmEvent::RxTimeout` if no frame was received within `timeout`.
+  pub fn with_timeout(mut self, timeout: Duration) -> RxJob {
+    self.timeout = Some(timeout);
+    self
+  }
+  /// Report at most one frame per `interval`.
+  pub fn with_throttle(mut self, interval: Duration) -> RxJob {
+    self.throttle = Some(interval);
+    self
+  }
+}
+
+/// Notification received from a broadcast manager socket, see `Msg::bcm_event`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BcmEvent {
+  /// the initial burst of the TX job with the identifier finished (`TX_EXPIRED`)
+  TxExpired(Id),
+  /// a frame of an RX job was received for the first time or its content changed (`RX_CHANGED`)
+  RxChanged(Frame),
+  /// no frame with the identifier was received within the timeout of its RX job (`RX_TIMEOUT`)
+  RxTimeout(Id),
+}
+
+/// Broadcast manager socket.
+pub struct Bcm {
+  pub(crate) fd: SocketFd,
+}
+impl Bcm {
+  /// Open a broadcast manager socket on the CAN device with the netdev name.
+  pub fn open(ifname: &str) -> io::Result<Bcm> {
+    let ifindex = crate::ifindex(ifname)?;
+    let bcm = Bcm { fd: SocketFd::open(libc::SOCK_DGRAM, libc::CAN_BCM)? };
+    unsafe {
+      let mut addr: libc::sockaddr_can = mem::zeroed();
+      addr.can_family = AF_CAN as u16;
+      addr.can_ifindex = ifindex;
+      if libc::connect(bcm.fd.raw(), &addr as *const libc::sockaddr_can as *const libc::sockaddr, mem::size_of::<libc::sockaddr_can>() as u32) != 0 {
+        return Err(io::Error::last_os_error());
+      }
+      Ok(bcm)
+    }
+  }
+  /// Creates or replaces the TX job for the identifier of the first frame (`TX_SETUP`)
+  /// and (re)starts its timers.
+  pub fn tx_setup(&self, job: &TxJob) -> io::Result<()> {
+    let mut head = BcmMsgHead {
+      opcode: TX_SETUP,
+      flags: SETTIMER | STARTTIMER,
+      count: job.count,
+      ival1: BcmTimeval::from_duration(job.ival1),
+      ival2: BcmTimeval::from_duration(job.ival2),
+      ..Default::default()
+    };
+    if job.notify_expired {
+      head.flags |= TX_COUNTEVT;
+    }
+    if job.announce {
+      head.flags |= TX_ANNOUNCE;
+    }
+    self.send_frames(head, &job.frames)
+  }
+  /// Replaces the frames of the running TX job for the identifier of the first frame
+  /// without touching its timers. If `announce` is set, the first new frame is sent immediately.
+  pub fn tx_update(&self, frames: &[Frame], announce: bool) -> io::Result<()> {
+    let head = BcmMsgHead {
+      opcode: TX_SETUP,
+      flags: if announce { TX_ANNOUNCE } else { 0 },
+      ..Default::default()
+    };
+    self.send_frames(head, frames)
+  }
+  /// Deletes the TX job for the identifier (`TX_DELETE`). `fd` selects the CAN FD job.
+  pub fn tx_delete(&self, id: Id, fd: bool) -> io::Result<()> {
+    let head = BcmMsgHead {
+      opcode: TX_DELETE,
+      flags: if fd { CAN_FD_FRAME } else { 0 },
+      can_id: id.can_id(),
+      ..Default::default()
+    };
+    self.send_head(&head, &[])
+  }
+  /// Reads the settings and frames of the TX job for the identifier (`TX_READ`).
+  /// `fd` selects the CAN FD job. The reply is read from the socket, so notifications which
+  /// arrive before it are returned along with the job, in the order they were received.
+  pub fn tx_read(&self, id: Id, fd: bool) -> io::Result<(TxJob, Vec<BcmEvent>)> {
+    let head = BcmMsgHead {
+      opcode: TX_READ,
+      flags: if fd { CAN_FD_FRAME } else { 0 },
+      can_id: id.can_id(),
+      ..Default::default()
+    };
+    self.send_head(&head, &[])?;
+    let mut buf = vec![0u8; HEAD_SIZE + MAX_NFRAMES * libc::CANFD_MTU];
+    let mut events = Vec::new();
+    loop {
+      let nbytes = unsafe { libc::read(self.fd.raw(), buf.as_mut_ptr() as *mut c_void, buf.len()) };
+      if nbytes < 0 {
+        return Err(io::Error::last_os_error());
+      }
+      let (reply, frames) = parse_reply(&buf[..nbytes as usize])?;
+      if reply.opcode == TX_STATUS && reply.can_id == head.can_id {
+        let job = TxJob {
+          frames,
+          count: reply.count,
+          ival1: reply.ival1.to_duration(),
+          ival2: reply.ival2.to_duration(),
+          notify_expired: reply.flags & TX_COUNTEVT != 0,
+          announce: reply.flags & TX_ANNOUNCE != 0,
+        };
+        return Ok((job, events));
+      }
+      events.extend(decode_event(&reply, frames.into_iter().next()));
+    }
+  }
+  /// Creates or replaces the RX job for the identifier (`RX_SETUP`).
+  pub fn rx_setup(&self, job: &RxJob) -> io::Result<()> {
+    let mut head = BcmMsgHead {
+      opcode: RX_SETUP,
+      flags: SETTIMER,
+      ival1: BcmTimeval::from_duration(job.timeout.unwrap_or_else(Duration::zero)),
+      ival2: BcmTimeval::from_duration(job.throttle.unwrap_or_else(Duration::zero)),
+      can_id: job.id.can_id(),
+      ..Default::default()
+    };
+    if job.timeout.is_some() {
+      head.flags |= STARTTIMER;
+    }
+    if job.check_dlc {
+      head.flags |= RX_CHECK_DLC;
+    }
+    if job.announce_resume {
+      head.flags |= RX_ANNOUNCE_RESUME;
+    }
+    match &job.mask {
+      Some(mask) => {
+        let frame = if job.fd {
+          Frame::Fd(CanFdFrame::new(job.id, mask)?)
+        } else {
+          Frame::Can(CanFrame::new(job.id, mask)?)
+        };
+        self.send_frames(head, &[frame])
+      }
+      None => {
+        head.flags |= RX_FILTER_ID;
+        if job.fd {
+          head.flags |= CAN_FD_FRAME;
+        }
+        self.send_head(&head, &[])
+      }
+    }
+  }
+  /// Deletes the RX job for the identifier (`RX_DELETE`). `fd` selects the CAN FD job.
+  pub fn rx_delete(&self, id: Id, fd: bool) -> io::Result<()> {
+    let head = BcmMsgHead {
+      opcode: RX_DELETE,
+      flags: if fd { CAN_FD_FRAME } else { 0 },
+      can_id: id.can_id(),
+      ..Default::default()
+    };
+    self.send_head(&head, &[])
+  }
+  /// Receives the next notification of the broadcast manager. Decode it with `Msg::bcm_event`.
+  pub fn recv(&self, msg: &mut Msg) -> io::Result<()> {
+    unsafe {
+      msg.reset();
+      msg.iov[0].iov_base = &mut msg.bcm as *mut BcmMsgHead as *mut c_void;
+      msg.iov[0].iov_len  = HEAD_SIZE;
+      msg.iov[1].iov_base = &mut msg.frame as *mut RawFrame as *mut c_void;
+      msg.iov[1].iov_len  = libc::CANFD_MTU;
+      msg.msg.msg_iovlen  = 2;
+      msg.proto           = libc::CAN_BCM;
+      let nbytes = libc::recvmsg(self.fd.raw(), &mut msg.msg, 0);
+      if nbytes < 0 {
+        return Err(io::Error::last_os_error());
+      }
+      msg.nbytes = nbytes as usize;
+    }
+    Ok(())
+  }
+  fn send_frames(&self, mut head: BcmMsgHead, frames: &[Frame]) -> io::Result<()> {
+    if frames.is_empty() || frames.len() > MAX_NFRAMES {
+      return Err(io::Error::new(io::ErrorKind::InvalidInput, "a BCM job takes 1 to 256 frames"));
+    }
+    let fd = matches!(frames[0], Frame::Fd(_));
+    let mut raw = Vec::with_capacity(frames.len() * libc::CANFD_MTU);
+    for frame in frames {
+      if matches!(frame, Frame::Xl(_)) || matches!(frame, Frame::Fd(_)) != fd {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "a BCM job takes either classic CAN or CAN FD frames"));
+      }
+      let (encoded, mtu) = frame.encode();
+      raw.extend_from_slice(unsafe { std::slice::from_raw_parts(&encoded as *const RawFrame as *const u8, mtu) });
+    }
+    head.can_id = frames[0].id().can_id();
+    head.nframes = frames.len() as u32;
+    if fd {
+      head.flags |= CAN_FD_FRAME;
+    }
+    self.send_head(&head, &raw)
+  }
+  fn send_head(&self, head: &BcmMsgHead, frames: &[u8]) -> io::Result<()> {
+    let mut buf = Vec::with_capacity(HEAD_SIZE + frames.len());
+    buf.extend_from_slice(unsafe { std::slice::from_raw_parts(head as *const BcmMsgHead as *const u8, HEAD_SIZE) });
+    buf.extend_from_slice(frames);
+    let nbytes = unsafe { libc::write(self.fd.raw(), buf.as_ptr() as *const c_void, buf.len()) };
+    if nbytes < 0 {
+      return Err(io::Error::last_os_error());
+    }
+    if nbytes as usize != buf.len() {
+      return Err(io::Error::new(io::ErrorKind::WriteZero, "BCM message was only partially written"));
+    }
+    Ok(())
+  }
+}
+
+fn parse_reply(buf: &[u8]) -> io::Result<(BcmMsgHead, Vec<Frame>)> {
+  if buf.len() < HEAD_SIZE {
+    return Err(io::Error::new(io::ErrorKind::InvalidData, "truncated BCM message"));
+  }
+  let head = unsafe { std::ptr::read_unaligned(buf.as_ptr() as *const BcmMsgHead) };
+  let mtu = if head.flags & CAN_FD_FRAME != 0 { libc::CANFD_MTU } else { libc::CAN_MTU };
+  let frames = buf[HEAD_SIZE..].chunks_exact(mtu).take(head.nframes as usize)
+    .filter_map(|chunk| decode_frame(chunk, head.flags & CAN_FD_FRAME != 0))
+    .collect();
+  Ok((head, frames))
+}
+
+fn decode_frame(raw: &[u8], fd: bool) -> Option<Frame> {
+  let mut frame: RawFrame = unsafe { mem::zeroed() };
+  unsafe {
+    std::ptr::copy_nonoverlapping(raw.as_ptr(), &mut frame as *mut RawFrame as *mut u8, raw.len().min(libc::CANFD_MTU));
+    let len = (frame.fd.len as usize).min(if fd { libc::CANFD_MAX_DLEN } else { libc::CAN_MAX_DLEN });
+    Frame::from_raw(frame.fd.can_id, frame.fd.flags, &frame.fd.data[..len], fd)
+  }
+}
+
+/// Decodes the message received by `Bcm::recv`, `frame` is the frame following the head.
+pub(crate) fn decode_event(head: &BcmMsgHead, frame: Option<Frame>) -> Option<BcmEvent> {
+  match head.opcode {
+    TX_EXPIRED => Some(BcmEvent::TxExpired(Id::from_can_id(head.can_id))),
+    RX_TIMEOUT => Some(BcmEvent::RxTimeout(Id::from_can_id(head.can_id))),
+    RX_CHANGED => frame.map(BcmEvent::RxChanged),
+    _ => None,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  #[test]
+  fn head_layout() {
+    // size of struct bcm_msg_head without frames
+    assert_eq!(HEAD_SIZE, if mem::size_of::<c_long>() == 8 { 56 } else { 40 });
+    assert_eq!(mem::align_of::<BcmMsgHead>(), 8);
+    let d = Duration::milliseconds(1500);
+    assert_eq!(BcmTimeval::from_duration(d).to_duration(), d);
+  }
+  #[test]
+  fn reply_parsing() {
+    let frame = Frame::Can(CanFrame::new(Id::Standard(0x321), &[1, 2, 3]).unwrap());
+    let head = BcmMsgHead { opcode: TX_STATUS, can_id: 0x321, nframes: 1, count: 3, ..Default::default() };
+    let mut buf = unsafe { std::slice::from_raw_parts(&head as *const BcmMsgHead as *const u8, HEAD_SIZE) }.to_vec();
+    let (raw, mtu) = frame.encode();
+    buf.extend_from_slice(unsafe { std::slice::from_raw_parts(&raw as *const RawFrame as *const u8, mtu) });
+    let (reply, frames) = parse_reply(&buf).unwrap();
+    assert_eq!(reply.count, 3);
+    assert_eq!(frames, vec![frame]);
+  }
+  #[test]
+  fn tx_read_keeps_notifications() {
+    let (fd, peer) = crate::socket_fd::socket_pair();
+    let bcm = Bcm { fd };
+    let send = |head: &BcmMsgHead, frame: Option<&Frame>| {
+      let mut buf = unsafe { std::slice::from_raw_parts(head as *const BcmMsgHead as *const u8, HEAD_SIZE) }.to_vec();
+      if let Some(frame) = frame {
+        let (raw, mtu) = frame.encode();
+        buf.extend_from_slice(unsafe { std::slice::from_raw_parts(&raw as *const RawFrame as *const u8, mtu) });
+      }
+      peer.send(&buf).unwrap();
+    };
+    let frame = Frame::Can(CanFrame::new(Id::Standard(0x321), &[1, 2, 3]).unwrap());
+    // the reply of the kernel is queued behind two notifications
+    send(&BcmMsgHead { opcode: RX_TIMEOUT, can_id: 0x42, ..Default::default() }, None);
+    send(&BcmMsgHead { opcode: TX_EXPIRED, can_id: 0x321, ..Default::default() }, None);
+    send(&BcmMsgHead { opcode: TX_STATUS, can_id: 0x321, nframes: 1, count: 3, ..Default::default() }, Some(&frame));
+    let (job, events) = bcm.tx_read(Id::Standard(0x321), false).unwrap();
+    assert_eq!(job.frames, vec![frame]);
+    assert_eq!(job.count, 3);
+    assert_eq!(events, vec![BcmEvent::RxTimeout(Id::Standard(0x42)), BcmEvent::TxExpired(Id::Standard(0x321))]);
+  }
+  #[test]
+  fn rx_events() {
+    let frame = Frame::Can(CanFrame::new(Id::Extended(0x1234), &[7]).unwrap());
+    let head = BcmMsgHead { opcode: RX_CHANGED, can_id: frame.can_id(), nframes: 1, ..Default::default() };
+    assert_eq!(decode_event(&head, Some(frame.clone())), Some(BcmEvent::RxChanged(frame)));
+    let head = BcmMsgHead { opcode: RX_TIMEOUT, can_id: 0x42, ..Default::default() };
+    assert_eq!(decode_event(&head, None), Some(BcmEvent::RxTimeout(Id::Standard(0x42))));
+    let job = RxJob::new(Id::Standard(0x42)).with_mask(&[0xff]).with_throttle(Duration::milliseconds(100));
+    assert_eq!(job.mask, Some(vec![0xff]));
+    assert_eq!(job.timeout, None);
+  }
+}
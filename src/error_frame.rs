@@ -0,0 +1,309 @@
+//! Decoding of CAN error frames (see `linux/can/error.h`).
+//!
+//! Error frames are only delivered if they were enabled with `Can::set_error_filter`.
+
+use crate::{ERR_FLAG, ERR_MASK};
+
+/// TX timeout (by netdevice driver)
+pub const ERR_TX_TIMEOUT: u32 = 0x00000001;
+/// lost arbitration
+pub const ERR_LOSTARB: u32 = 0x00000002;
+/// controller problems
+pub const ERR_CRTL: u32 = 0x00000004;
+/// protocol violations
+pub const ERR_PROT: u32 = 0x00000008;
+/// transceiver status
+pub const ERR_TRX: u32 = 0x00000010;
+/// received no ACK on transmission
+pub const ERR_ACK: u32 = 0x00000020;
+/// bus off
+pub const ERR_BUSOFF: u32 = 0x00000040;
+/// bus error (may flood!)
+pub const ERR_BUSERROR: u32 = 0x00000080;
+/// controller restarted
+pub const ERR_RESTARTED: u32 = 0x00000100;
+/// TX error counter / `data[6]`, RX error counter / `data[7]`
+pub const ERR_CNT: u32 = 0x00000200;
+
+/// Controller problems (`data[1]`).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ControllerError {
+  /// RX buffer overflow
+  pub rx_overflow: bool,
+  /// TX buffer overflow
+  pub tx_overflow: bool,
+  /// reached warning level for RX errors
+  pub rx_warning: bool,
+  /// reached warning level for TX errors
+  pub tx_warning: bool,
+  /// reached error passive status RX
+  pub rx_passive: bool,
+  /// reached error passive status TX
+  pub tx_passive: bool,
+  /// recovered to error active state
+  pub active: bool,
+}
+impl ControllerError {
+  fn decode(status: u8) -> ControllerError {
+    ControllerError {
+      rx_overflow: status & 0x01 != 0,
+      tx_overflow: status & 0x02 != 0,
+      rx_warning:  status & 0x04 != 0,
+      tx_warning:  status & 0x08 != 0,
+      rx_passive:  status & 0x10 != 0,
+      tx_passive:  status & 0x20 != 0,
+      active:      status & 0x40 != 0,
+    }
+  }
+}
+
+/// Location of a protocol violation in the frame (`data[3]`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProtocolLocation {
+  /// unspecified
+  Unspecified,
+  /// start of frame
+  Sof,
+  /// ID bits 28 - 21 (SFF: 10 - 3)
+  Id28To21,
+  /// ID bits 20 - 18 (SFF: 2 - 0)
+  Id20To18,
+  /// substitute RTR (SFF: RTR)
+  Srtr,
+  /// identifier extension
+  Ide,
+  /// ID bits 17-13
+  Id17To13,
+  /// ID bits 12-5
+  Id12To05,
+  /// ID bits 4-0
+  Id04To00,
+  /// RTR
+  Rtr,
+  /// reserved bit 1
+  Res1,
+  /// reserved bit 0
+  Res0,
+  /// data length code
+  Dlc,
+  /// data section
+  Data,
+  /// CRC sequence
+  CrcSeq,
+  /// CRC delimiter
+  CrcDel,
+  /// ACK slot
+  Ack,
+  /// ACK delimiter
+  AckDel,
+  /// end of frame
+  Eof,
+  /// intermission
+  Intermission,
+  /// location unknown to this crate
+  Other(u8),
+}
+impl ProtocolLocation {
+  fn decode(location: u8) -> ProtocolLocation {
+    match location {
+      0x00 => ProtocolLocation::Unspecified,
+      0x03 => ProtocolLocation::Sof,
+      0x02 => ProtocolLocation::Id28To21,
+      0x06 => ProtocolLocation::Id20To18,
+      0x04 => ProtocolLocation::Srtr,
+      0x05 => ProtocolLocation::Ide,
+      0x07 => ProtocolLocation::Id17To13,
+      0x0F => ProtocolLocation::Id12To05,
+      0x0E => ProtocolLocation::Id04To00,
+      0x0C => ProtocolLocation::Rtr,
+      0x0D => ProtocolLocation::Res1,
+      0x09 => ProtocolLocation::Res0,
+      0x0B => ProtocolLocation::Dlc,
+      0x0A => ProtocolLocation::Data,
+      0x08 => ProtocolLocation::CrcSeq,
+      0x18 => ProtocolLocation::CrcDel,
+      0x19 => ProtocolLocation::Ack,
+      0x1B => ProtocolLocation::AckDel,
+      0x1A => ProtocolLocation::Eof,
+      0x12 => ProtocolLocation::Intermission,
+      other => ProtocolLocation::Other(other),
+    }
+  }
+}
+
+/// Protocol violation (`data[2]` and `data[3]`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ProtocolError {
+  /// single bit error
+  pub bit: bool,
+  /// frame format error
+  pub form: bool,
+  /// bit stuffing error
+  pub stuff: bool,
+  /// unable to send dominant bit
+  pub bit0: bool,
+  /// unable to send recessive bit
+  pub bit1: bool,
+  /// bus overload
+  pub overload: bool,
+  /// active error announcement
+  pub active: bool,
+  /// error occurred on transmission
+  pub tx: bool,
+  /// location of the violation
+  pub location: ProtocolLocation,
+}
+impl ProtocolError {
+  fn decode(kind: u8, location: u8) -> ProtocolError {
+    ProtocolError {
+      bit:      kind & 0x01 != 0,
+      form:     kind & 0x02 != 0,
+      stuff:    kind & 0x04 != 0,
+      bit0:     kind & 0x08 != 0,
+      bit1:     kind & 0x10 != 0,
+      overload: kind & 0x20 != 0,
+      active:   kind & 0x40 != 0,
+      tx:       kind & 0x80 != 0,
+      location: ProtocolLocation::decode(location),
+    }
+  }
+}
+
+/// Status of a single CAN wire.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WireStatus {
+  /// no problem reported
+  Unspecified,
+  /// wire not connected
+  NoWire,
+  /// short to battery voltage
+  ShortToBat,
+  /// short to supply voltage
+  ShortToVcc,
+  /// short to ground
+  ShortToGnd,
+  /// short to the other wire (only reported for CANL)
+  ShortToCanh,
+  /// status unknown to this crate
+  Other(u8),
+}
+
+/// Transceiver status (`data[4]`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TransceiverError {
+  /// status of the CANH wire
+  pub canh: WireStatus,
+  /// status of the CANL wire
+  pub canl: WireStatus,
+}
+impl TransceiverError {
+  fn decode(status: u8) -> TransceiverError {
+    let canh = match status & 0x0f {
+      0x00 => WireStatus::Unspecified,
+      0x04 => WireStatus::NoWire,
+      0x05 => WireStatus::ShortToBat,
+      0x06 => WireStatus::ShortToVcc,
+      0x07 => WireStatus::ShortToGnd,
+      other => WireStatus::Other(other),
+    };
+    let canl = match status & 0xf0 {
+      0x00 => WireStatus::Unspecified,
+      0x40 => WireStatus::NoWire,
+      0x50 => WireStatus::ShortToBat,
+      0x60 => WireStatus::ShortToVcc,
+      0x70 => WireStatus::ShortToGnd,
+      0x80 => WireStatus::ShortToCanh,
+      other => WireStatus::Other(other),
+    };
+    TransceiverError { canh, canl }
+  }
+}
+
+/// TX and RX error counters of the controller (`data[6]` and `data[7]`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ErrorCounters {
+  /// transmit error counter
+  pub tx: u8,
+  /// receive error counter
+  pub rx: u8,
+}
+
+/// Decoded CAN error frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ErrorFrame {
+  /// error classes (`ERR_TX_TIMEOUT`, `ERR_LOSTARB`, ...)
+  pub class: u32,
+  /// TX timeout (by netdevice driver)
+  pub tx_timeout: bool,
+  /// bit number in which arbitration was lost, `Some(0)` if the bit is unspecified
+  pub lost_arbitration: Option<u8>,
+  /// controller problems
+  pub controller: Option<ControllerError>,
+  /// protocol violations
+  pub protocol: Option<ProtocolError>,
+  /// transceiver status
+  pub transceiver: Option<TransceiverError>,
+  /// received no ACK on transmission
+  pub no_ack: bool,
+  /// controller went bus off
+  pub bus_off: bool,
+  /// bus error
+  pub bus_error: bool,
+  /// controller restarted
+  pub restarted: bool,
+  /// error counters, available if the driver reports them (`ERR_CNT`) or along with controller problems
+  pub counters: Option<ErrorCounters>,
+}
+impl ErrorFrame {
+  /// Decodes an error frame from its raw `can_id` and payload.
+  /// Returns None if `ERR_FLAG` is not set in `can_id`.
+  pub fn decode(can_id: u32, data: &[u8]) -> Option<ErrorFrame> {
+    if can_id & ERR_FLAG == 0 {
+      return None;
+    }
+    let mut d = [0u8; 8];
+    let len = data.len().min(8);
+    d[..len].copy_from_slice(&data[..len]);
+    let class = can_id & ERR_MASK;
+    let has = |flag: u32| class & flag != 0;
+    Some(ErrorFrame {
+      class,
+      tx_timeout:       has(ERR_TX_TIMEOUT),
+      lost_arbitration: if has(ERR_LOSTARB) { Some(d[0]) } else { None },
+      controller:       if has(ERR_CRTL) { Some(ControllerError::decode(d[1])) } else { None },
+      protocol:         if has(ERR_PROT) { Some(ProtocolError::decode(d[2], d[3])) } else { None },
+      transceiver:      if has(ERR_TRX) { Some(TransceiverError::decode(d[4])) } else { None },
+      no_ack:           has(ERR_ACK),
+      bus_off:          has(ERR_BUSOFF),
+      bus_error:        has(ERR_BUSERROR),
+      restarted:        has(ERR_RESTARTED),
+      counters:         if has(ERR_CNT) || has(ERR_CRTL) { Some(ErrorCounters { tx: d[6], rx: d[7] }) } else { None },
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  #[test]
+  fn decode_controller_and_protocol_errors() {
+    let frame = ErrorFrame::decode(ERR_FLAG | ERR_CRTL | ERR_PROT | ERR_CNT, &[0, 0x24, 0x08, 0x0a, 0, 0, 97, 3]).unwrap();
+    let controller = frame.controller.unwrap();
+    assert!(controller.rx_warning && controller.tx_passive && !controller.rx_overflow);
+    let protocol = frame.protocol.unwrap();
+    assert!(protocol.bit0 && !protocol.tx);
+    assert_eq!(protocol.location, ProtocolLocation::Data);
+    assert_eq!(frame.counters, Some(ErrorCounters { tx: 97, rx: 3 }));
+    assert_eq!(frame.lost_arbitration, None);
+    assert!(!frame.bus_off);
+  }
+  #[test]
+  fn decode_transceiver_and_bus_off() {
+    let frame = ErrorFrame::decode(ERR_FLAG | ERR_TRX | ERR_BUSOFF | ERR_LOSTARB, &[5, 0, 0, 0, 0x74, 0, 0, 0]).unwrap();
+    assert_eq!(frame.transceiver, Some(TransceiverError { canh: WireStatus::NoWire, canl: WireStatus::ShortToGnd }));
+    assert_eq!(frame.lost_arbitration, Some(5));
+    assert!(frame.bus_off);
+    assert_eq!(frame.counters, None);
+    assert_eq!(ErrorFrame::decode(0x123, &[]), None);
+  }
+}
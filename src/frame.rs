@@ -0,0 +1,429 @@
+//! Owned CAN frame types.
+//!
+//! In contrast to `Msg`, which is a receive buffer that only `Can::recv` can fill,
+//! these types can be built by the application and passed to `Can::send_frame`.
+
+use std::io;
+
+use crate::{EFF_FLAG, EFF_MASK, ERR_FLAG, RTR_FLAG, SFF_MASK};
+
+/// bit rate switch (second bitrate for payload data)
+pub const CANFD_BRS: u8 = 0x01;
+/// error state indicator of the transmitting node
+pub const CANFD_ESI: u8 = 0x02;
+/// mandatory CAN XL frame flag (CAN XL frames are distinguished by this flag)
+pub const CANXL_XLF: u8 = 0x80;
+/// simple extended content (security/segmentation)
+pub const CANXL_SEC: u8 = 0x01;
+
+const CANXL_PRIO_MASK: u32 = 0x7ff;
+const CANXL_VCID_OFFSET: u32 = 16;
+pub(crate) const CANXL_HDR_SIZE: usize = 12;
+pub(crate) const CANXL_MAX_DLEN: usize = 2048;
+pub(crate) const CANXL_MTU: usize = CANXL_HDR_SIZE + CANXL_MAX_DLEN;
+
+/// `struct canxl_frame` (not provided by libc).
+#[repr(C)]
+#[derive(Clone, Copy)]
+#[allow(non_camel_case_types)]
+pub(crate) struct canxl_frame {
+  pub prio: u32,
+  pub flags: u8,
+  pub sdt: u8,
+  pub len: u16,
+  pub af: u32,
+  pub data: [u8; CANXL_MAX_DLEN],
+}
+
+/// Buffer holding any frame the kernel may pass to a raw socket.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub(crate) union RawFrame {
+  pub fd: libc::canfd_frame,
+  pub xl: canxl_frame,
+}
+impl RawFrame {
+  /// Returns true if `nbytes` bytes of the buffer form a CAN XL frame.
+  pub(crate) fn is_xl(&self, nbytes: usize) -> bool {
+    nbytes > CANXL_HDR_SIZE && nbytes <= CANXL_MTU && unsafe { self.xl.flags } & CANXL_XLF != 0
+  }
+}
+
+fn invalid_input(what: &'static str) -> io::Error {
+  io::Error::new(io::ErrorKind::InvalidInput, what)
+}
+
+/// CAN identifier.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Id {
+  /// 11 bit standard identifier
+  Standard(u16),
+  /// 29 bit extended identifier
+  Extended(u32),
+}
+impl Id {
+  /// Splits a raw `can_id` as used by the kernel into the identifier.
+  /// `RTR_FLAG` and `ERR_FLAG` are ignored.
+  pub fn from_can_id(can_id: u32) -> Id {
+    if can_id & EFF_FLAG != 0 {
+      Id::Extended(can_id & EFF_MASK)
+    } else {
+      Id::Standard((can_id & SFF_MASK) as u16)
+    }
+  }
+  /// Returns the identifier as raw `can_id` (with `EFF_FLAG` set for extended identifiers).
+  pub fn can_id(&self) -> u32 {
+    match *self {
+      Id::Standard(id) => id as u32,
+      Id::Extended(id) => id | EFF_FLAG,
+    }
+  }
+  /// Returns the identifier without any flags.
+  pub fn raw(&self) -> u32 {
+    match *self {
+      Id::Standard(id) => id as u32,
+      Id::Extended(id) => id,
+    }
+  }
+  fn validate(&self) -> io::Result<()> {
+    match *self {
+      Id::Standard(id) if id as u32 > SFF_MASK => Err(invalid_input("standard CAN id exceeds 11 bits")),
+      Id::Extended(id) if id > EFF_MASK => Err(invalid_input("extended CAN id exceeds 29 bits")),
+      _ => Ok(()),
+    }
+  }
+}
+
+/// Classic CAN frame (data or remote frame).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CanFrame {
+  id: Id,
+  rtr: bool,
+  len: u8,
+  data: [u8; 8],
+}
+impl CanFrame {
+  /// Creates a data frame. At most 8 data bytes are allowed.
+  pub fn new(id: Id, data: &[u8]) -> io::Result<CanFrame> {
+    id.validate()?;
+    if data.len() > 8 {
+      return Err(invalid_input("CAN frames carry at most 8 data bytes"));
+    }
+    let mut frame = CanFrame { id, rtr: false, len: data.len() as u8, data: [0; 8] };
+    frame.data[..data.len()].copy_from_slice(data);
+    Ok(frame)
+  }
+  /// Creates a remote transmission request with the requested DLC (0 to 8).
+  pub fn remote(id: Id, dlc: u8) -> io::Result<CanFrame> {
+    id.validate()?;
+    if dlc > 8 {
+      return Err(invalid_input("the DLC of a CAN frame is at most 8"));
+    }
+    Ok(CanFrame { id, rtr: true, len: dlc, data: [0; 8] })
+  }
+  /// Get CAN identifier.
+  pub fn id(&self) -> Id {
+    self.id
+  }
+  /// Get the raw `can_id` including `EFF_FLAG` and `RTR_FLAG`.
+  pub fn can_id(&self) -> u32 {
+    if self.rtr {
+      self.id.can_id() | RTR_FLAG
+    } else {
+      self.id.can_id()
+    }
+  }
+  /// Returns true for remote transmission requests.
+  pub fn is_remote(&self) -> bool {
+    self.rtr
+  }
+  /// Get DLC.
+  pub fn dlc(&self) -> u8 {
+    self.len
+  }
+  /// Get the payload. Remote frames have no payload.
+  pub fn data(&self) -> &[u8] {
+    if self.rtr {
+      &[]
+    } else {
+      &self.data[..self.len as usize]
+    }
+  }
+}
+
+/// CAN FD frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CanFdFrame {
+  id: Id,
+  flags: u8,
+  len: u8,
+  data: [u8; 64],
+}
+impl CanFdFrame {
+  /// Creates a CAN FD frame. The payload length must be one a CAN FD DLC can express
+  /// (0 to 8, 12, 16, 20, 24, 32, 48 or 64 bytes).
+  pub fn new(id: Id, data: &[u8]) -> io::Result<CanFdFrame> {
+    id.validate()?;
+    if !matches!(data.len(), 0..=8 | 12 | 16 | 20 | 24 | 32 | 48 | 64) {
+      return Err(invalid_input("invalid CAN FD payload length"));
+    }
+    let mut frame = CanFdFrame { id, flags: 0, len: data.len() as u8, data: [0; 64] };
+    frame.data[..data.len()].copy_from_slice(data);
+    Ok(frame)
+  }
+  /// Sets the bit rate switch flag.
+  pub fn with_brs(mut self, brs: bool) -> CanFdFrame {
+    self.set_flag(CANFD_BRS, brs);
+    self
+  }
+  /// Sets the error state indicator flag.
+  pub fn with_esi(mut self, esi: bool) -> CanFdFrame {
+    self.set_flag(CANFD_ESI, esi);
+    self
+  }
+  fn set_flag(&mut self, flag: u8, on: bool) {
+    if on {
+      self.flags |= flag;
+    } else {
+      self.flags &= !flag;
+    }
+  }
+  /// Get CAN identifier.
+  pub fn id(&self) -> Id {
+    self.id
+  }
+  /// Get the raw `can_id` including `EFF_FLAG`.
+  pub fn can_id(&self) -> u32 {
+    self.id.can_id()
+  }
+  /// Get CAN FD flags.
+  pub fn flags(&self) -> u8 {
+    self.flags
+  }
+  /// Returns true if the bit rate switch flag is set.
+  pub fn brs(&self) -> bool {
+    self.flags & CANFD_BRS != 0
+  }
+  /// Returns true if the error state indicator flag is set.
+  pub fn esi(&self) -> bool {
+    self.flags & CANFD_ESI != 0
+  }
+  /// Get the payload.
+  pub fn data(&self) -> &[u8] {
+    &self.data[..self.len as usize]
+  }
+}
+
+/// CAN XL frame.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CanXlFrame {
+  prio: u16,
+  vcid: u8,
+  sec: bool,
+  sdt: u8,
+  af: u32,
+  data: Vec<u8>,
+}
+impl CanXlFrame {
+  /// Creates a CAN XL frame with the 11 bit priority, the SDU type, the acceptance field
+  /// and 1 to 2048 data bytes.
+  pub fn new(prio: u16, sdt: u8, af: u32, data: &[u8]) -> io::Result<CanXlFrame> {
+    if prio as u32 > CANXL_PRIO_MASK {
+      return Err(invalid_input("CAN XL priority exceeds 11 bits"));
+    }
+    if data.is_empty() || data.len() > CANXL_MAX_DLEN {
+      return Err(invalid_input("CAN XL frames carry 1 to 2048 data bytes"));
+    }
+    Ok(CanXlFrame { prio, vcid: 0, sec: false, sdt, af, data: data.to_vec() })
+  }
+  /// Sets the virtual CAN network id. It is only transmitted if the socket passes it
+  /// (see `XlVcidOptions::tx_pass`).
+  pub fn with_vcid(mut self, vcid: u8) -> CanXlFrame {
+    self.vcid = vcid;
+    self
+  }
+  /// Sets the simple extended content flag.
+  pub fn with_sec(mut self, sec: bool) -> CanXlFrame {
+    self.sec = sec;
+    self
+  }
+  pub(crate) fn from_raw(raw: &canxl_frame) -> CanXlFrame {
+    let len = (raw.len as usize).clamp(1, CANXL_MAX_DLEN);
+    CanXlFrame {
+      prio: (raw.prio & CANXL_PRIO_MASK) as u16,
+      vcid: (raw.prio >> CANXL_VCID_OFFSET) as u8,
+      sec:  raw.flags & CANXL_SEC != 0,
+      sdt:  raw.sdt,
+      af:   raw.af,
+      data: raw.data[..len].to_vec(),
+    }
+  }
+  /// Get the 11 bit priority.
+  pub fn prio(&self) -> u16 {
+    self.prio
+  }
+  /// Get the virtual CAN network id.
+  pub fn vcid(&self) -> u8 {
+    self.vcid
+  }
+  /// Returns true if the simple extended content flag is set.
+  pub fn sec(&self) -> bool {
+    self.sec
+  }
+  /// Get the SDU (service data unit) type.
+  pub fn sdt(&self) -> u8 {
+    self.sdt
+  }
+  /// Get the acceptance field.
+  pub fn af(&self) -> u32 {
+    self.af
+  }
+  /// Get the payload.
+  pub fn data(&self) -> &[u8] {
+    &self.data
+  }
+}
+
+/// Either a classic CAN, a CAN FD or a CAN XL frame.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Frame {
+  /// Classic CAN frame
+  Can(CanFrame),
+  /// CAN FD frame
+  Fd(CanFdFrame),
+  /// CAN XL frame
+  Xl(CanXlFrame),
+}
+impl Frame {
+  /// Builds a frame from the raw fields of a received frame. Returns None for error frames.
+  pub(crate) fn from_raw(can_id: u32, flags: u8, data: &[u8], fd: bool) -> Option<Frame> {
+    if can_id & ERR_FLAG != 0 {
+      return None;
+    }
+    let id = Id::from_can_id(can_id);
+    if fd {
+      let mut frame = CanFdFrame { id, flags: flags & (CANFD_BRS | CANFD_ESI), len: data.len() as u8, data: [0; 64] };
+      frame.data[..data.len()].copy_from_slice(data);
+      Some(Frame::Fd(frame))
+    } else if can_id & RTR_FLAG != 0 {
+      Some(Frame::Can(CanFrame { id, rtr: true, len: data.len() as u8, data: [0; 8] }))
+    } else {
+      let mut frame = CanFrame { id, rtr: false, len: data.len() as u8, data: [0; 8] };
+      frame.data[..data.len()].copy_from_slice(data);
+      Some(Frame::Can(frame))
+    }
+  }
+  /// Returns the frame in its kernel layout and the number of bytes to write
+  /// (`CAN_MTU` for classic frames, whose layout is a prefix of `struct canfd_frame`,
+  /// `CANFD_MTU` for CAN FD frames and the header plus the payload for CAN XL frames).
+  pub(crate) fn encode(&self) -> (RawFrame, usize) {
+    let mut raw: RawFrame = unsafe { std::mem::zeroed() };
+    match self {
+      Frame::Can(frame) => {
+        let fd = unsafe { &mut raw.fd };
+        fd.can_id = frame.can_id();
+        fd.len = frame.dlc();
+        fd.data[..frame.data().len()].copy_from_slice(frame.data());
+        (raw, libc::CAN_MTU)
+      }
+      Frame::Fd(frame) => {
+        let fd = unsafe { &mut raw.fd };
+        fd.can_id = frame.can_id();
+        fd.len = frame.data().len() as u8;
+        fd.flags = frame.flags();
+        fd.data[..frame.data().len()].copy_from_slice(frame.data());
+        (raw, libc::CANFD_MTU)
+      }
+      Frame::Xl(frame) => {
+        let xl = unsafe { &mut raw.xl };
+        xl.prio = frame.prio as u32 | (frame.vcid as u32) << CANXL_VCID_OFFSET;
+        xl.flags = CANXL_XLF | if frame.sec { CANXL_SEC } else { 0 };
+        xl.sdt = frame.sdt;
+        xl.len = frame.data.len() as u16;
+        xl.af = frame.af;
+        xl.data[..frame.data.len()].copy_from_slice(&frame.data);
+        (raw, CANXL_HDR_SIZE + frame.data.len())
+      }
+    }
+  }
+  /// Get CAN identifier. CAN XL frames have no identifier, their priority is returned as standard identifier.
+  pub fn id(&self) -> Id {
+    match self {
+      Frame::Can(frame) => frame.id(),
+      Frame::Fd(frame) => frame.id(),
+      Frame::Xl(frame) => Id::Standard(frame.prio()),
+    }
+  }
+  /// Get the raw `can_id` including flags (the priority for CAN XL frames).
+  pub fn can_id(&self) -> u32 {
+    match self {
+      Frame::Can(frame) => frame.can_id(),
+      Frame::Fd(frame) => frame.can_id(),
+      Frame::Xl(frame) => frame.prio() as u32,
+    }
+  }
+  /// Get the payload.
+  pub fn data(&self) -> &[u8] {
+    match self {
+      Frame::Can(frame) => frame.data(),
+      Frame::Fd(frame) => frame.data(),
+      Frame::Xl(frame) => frame.data(),
+    }
+  }
+}
+impl From<CanFrame> for Frame {
+  fn from(frame: CanFrame) -> Frame {
+    Frame::Can(frame)
+  }
+}
+impl From<CanFdFrame> for Frame {
+  fn from(frame: CanFdFrame) -> Frame {
+    Frame::Fd(frame)
+  }
+}
+impl From<CanXlFrame> for Frame {
+  fn from(frame: CanXlFrame) -> Frame {
+    Frame::Xl(frame)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  #[test]
+  fn validates_constructors() {
+    assert!(CanFrame::new(Id::Standard(0x800), &[]).is_err());
+    assert!(CanFrame::new(Id::Extended(0x2000_0000), &[]).is_err());
+    assert!(CanFrame::new(Id::Standard(0x7ff), &[0; 9]).is_err());
+    assert!(CanFrame::remote(Id::Standard(0x7ff), 9).is_err());
+    assert!(CanFdFrame::new(Id::Standard(0x123), &[0; 9]).is_err());
+    assert!(CanFdFrame::new(Id::Standard(0x123), &[0; 65]).is_err());
+    assert!(CanFdFrame::new(Id::Standard(0x123), &[0; 48]).is_ok());
+    assert!(CanXlFrame::new(0x800, 0, 0, &[0]).is_err());
+    assert!(CanXlFrame::new(0x7ff, 0, 0, &[]).is_err());
+    assert!(CanXlFrame::new(0x7ff, 0, 0, &[0; 2049]).is_err());
+    assert!(CanXlFrame::new(0x7ff, 0, 0, &[0; 2048]).is_ok());
+  }
+  #[test]
+  fn xl_encoding() {
+    let frame = CanXlFrame::new(0x242, 0x03, 0xdeadbeef, &[1, 2, 3]).unwrap().with_vcid(0x11).with_sec(true);
+    let (raw, mtu) = Frame::Xl(frame.clone()).encode();
+    assert_eq!(mtu, CANXL_HDR_SIZE + 3);
+    assert!(raw.is_xl(mtu));
+    assert_eq!(unsafe { raw.xl.prio }, 0x0011_0242);
+    assert_eq!(CanXlFrame::from_raw(unsafe { &raw.xl }), frame);
+    let (raw, mtu) = Frame::Can(CanFrame::new(Id::Standard(0x1), &[8; 8]).unwrap()).encode();
+    assert!(!raw.is_xl(mtu));
+  }
+  #[test]
+  fn raw_can_id_round_trip() {
+    let frame = CanFrame::remote(Id::Extended(0x1234567), 2).unwrap();
+    assert_eq!(frame.can_id(), 0x1234567 | EFF_FLAG | RTR_FLAG);
+    assert!(frame.data().is_empty());
+    assert_eq!(Frame::from_raw(frame.can_id(), 0, &[0, 0], false), Some(Frame::Can(frame)));
+    let frame = CanFdFrame::new(Id::Standard(0x42), &[1, 2, 3]).unwrap().with_brs(true);
+    assert_eq!(frame.flags(), CANFD_BRS);
+    assert_eq!(Frame::from_raw(0x42, CANFD_BRS, &[1, 2, 3], true), Some(Frame::Fd(frame)));
+    assert_eq!(Frame::from_raw(ERR_FLAG | 0x4, 0, &[0; 8], false), None);
+  }
+}
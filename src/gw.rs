@@ -0,0 +1,463 @@
+//! Rules of the kernel CAN gateway (`CAN_GW`), what `cangw` from can-utils does.
+//!
+//! The gateway forwards frames between two CAN interfaces from within the kernel and can
+//! modify the forwarded frames. Changing the rules requires `CAP_NET_ADMIN`.
+
+use std::io;
+use std::mem;
+
+use crate::netlink::{self, NlMsg, Netlink, NLM_F_DUMP};
+use crate::{Filter, AF_CAN};
+
+// Constants stolen from linux/rtnetlink.h and linux/can/gw.h
+const RTM_NEWROUTE: u16 = 24;
+const RTM_DELROUTE: u16 = 25;
+const RTM_GETROUTE: u16 = 26;
+
+const CGW_TYPE_CAN_CAN: u8 = 1;
+
+const CGW_FLAGS_CAN_ECHO: u16 = 0x01;
+const CGW_FLAGS_CAN_SRC_TSTAMP: u16 = 0x02;
+const CGW_FLAGS_CAN_IIF_TX_OK: u16 = 0x04;
+const CGW_FLAGS_CAN_FD: u16 = 0x08;
+
+const CGW_MOD_AND: u16 = 1;
+const CGW_MOD_OR: u16 = 2;
+const CGW_MOD_XOR: u16 = 3;
+const CGW_MOD_SET: u16 = 4;
+const CGW_CS_XOR: u16 = 5;
+const CGW_CS_CRC8: u16 = 6;
+const CGW_HANDLED: u16 = 7;
+const CGW_DROPPED: u16 = 8;
+const CGW_SRC_IF: u16 = 9;
+const CGW_DST_IF: u16 = 10;
+const CGW_FILTER: u16 = 11;
+const CGW_DELETED: u16 = 12;
+const CGW_LIM_HOPS: u16 = 13;
+const CGW_MOD_UID: u16 = 14;
+const CGW_FDMOD_AND: u16 = 15;
+const CGW_FDMOD_OR: u16 = 16;
+const CGW_FDMOD_XOR: u16 = 17;
+const CGW_FDMOD_SET: u16 = 18;
+
+const CGW_MOD_ID: u8 = 0x01;
+const CGW_MOD_LEN: u8 = 0x02;
+const CGW_MOD_DATA: u8 = 0x04;
+const CGW_MOD_FLAGS: u8 = 0x08;
+
+const CGW_CRC8PRF_UNSPEC: u8 = 0;
+const CGW_CRC8PRF_1U8: u8 = 1;
+const CGW_CRC8PRF_16U8: u8 = 2;
+const CGW_CRC8PRF_SFFID_XOR: u8 = 3;
+
+// NLA_ALIGN(sizeof(struct can_frame) + 1) and NLA_ALIGN(sizeof(struct canfd_frame) + 1)
+const CGW_MODATTR_LEN: usize = 20;
+const CGW_FDMODATTR_LEN: usize = 76;
+// sizeof(struct cgw_csum_crc8)
+const CGW_CS_CRC8_LEN: usize = 282;
+
+/// `struct rtcanmsg`
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct RtCanMsg {
+  can_family: u8,
+  gwtype: u8,
+  flags: u16,
+}
+
+/// Modification of the forwarded frames, the fields which are None are not modified.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct GwMod {
+  /// operand for the CAN ID, including the `EFF_FLAG` and `RTR_FLAG`
+  pub can_id: Option<u32>,
+  /// operand for the data length
+  pub len: Option<u8>,
+  /// operand for the CAN FD flags, only for rules with `GwRule::fd`
+  pub flags: Option<u8>,
+  /// operand for the data, at most 8 bytes or 64 bytes for rules with `GwRule::fd`
+  pub data: Option<Vec<u8>>,
+}
+impl GwMod {
+  // struct cgw_frame_mod and struct cgw_fdframe_mod
+  fn encode(&self, fd: bool) -> io::Result<Vec<u8>> {
+    let (mtu, attr_len) = if fd { (libc::CANFD_MTU, CGW_FDMODATTR_LEN) } else { (libc::CAN_MTU, CGW_MODATTR_LEN) };
+    let mut buf = vec![0u8; attr_len];
+    let mut modtype = 0;
+    if let Some(can_id) = self.can_id {
+      modtype |= CGW_MOD_ID;
+      buf[..4].copy_from_slice(&can_id.to_ne_bytes());
+    }
+    if let Some(len) = self.len {
+      modtype |= CGW_MOD_LEN;
+      buf[4] = len;
+    }
+    if let Some(flags) = self.flags {
+      if !fd {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "CAN FD flags can only be modified by CAN FD rules"));
+      }
+      modtype |= CGW_MOD_FLAGS;
+      buf[5] = flags;
+    }
+    if let Some(data) = &self.data {
+      if data.len() > mtu - 8 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "modification data exceeds the frame size"));
+      }
+      modtype |= CGW_MOD_DATA;
+      buf[8..8 + data.len()].copy_from_slice(data);
+    }
+    buf[mtu] = modtype;
+    Ok(buf)
+  }
+  fn decode(buf: &[u8], fd: bool) -> Option<GwMod> {
+    let mtu = if fd { libc::CANFD_MTU } else { libc::CAN_MTU };
+    let modtype = *buf.get(mtu)?;
+    Some(GwMod {
+      can_id: if modtype & CGW_MOD_ID != 0 { netlink::attr_u32(buf) } else { None },
+      len:    if modtype & CGW_MOD_LEN != 0 { Some(buf[4]) } else { None },
+      flags:  if modtype & CGW_MOD_FLAGS != 0 { Some(buf[5]) } else { None },
+      data:   if modtype & CGW_MOD_DATA != 0 { Some(buf[8..mtu].to_vec()) } else { None },
+    })
+  }
+}
+
+/// XOR checksum over the data bytes `from` to `to`, stored in the data byte `result`.
+/// Negative indexes count from the end of the data.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct XorChecksum {
+  /// index of the first data byte
+  pub from: i8,
+  /// index of the last data byte
+  pub to: i8,
+  /// index of the data byte which receives the checksum
+  pub result: i8,
+  /// initial value
+  pub init: u8,
+}
+
+/// Additional input of the CRC8 checksum.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Crc8Profile {
+  /// no additional input
+  Unspecified,
+  /// a fixed byte
+  Fixed(u8),
+  /// one of 16 bytes, selected by the low nibble of `data[1]`
+  Indexed([u8; 16]),
+  /// the XOR of both bytes of the standard CAN ID
+  SffIdXor,
+}
+
+/// CRC8 checksum over the data bytes `from` to `to`, stored in the data byte `result`.
+/// Negative indexes count from the end of the data.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Crc8Checksum {
+  /// index of the first data byte
+  pub from: i8,
+  /// index of the last data byte
+  pub to: i8,
+  /// index of the data byte which receives the checksum
+  pub result: i8,
+  /// initial value
+  pub init: u8,
+  /// value XORed with the final CRC
+  pub final_xor: u8,
+  /// lookup table of the CRC polynomial
+  pub table: [u8; 256],
+  /// additional input
+  pub profile: Crc8Profile,
+}
+impl Crc8Checksum {
+  /// Creates a CRC8 checksum with the (MSB first) polynomial, e.g. 0x1d for SAE J1850.
+  pub fn new(from: i8, to: i8, result: i8, polynomial: u8) -> Crc8Checksum {
+    let mut table = [0u8; 256];
+    for (i, entry) in table.iter_mut().enumerate() {
+      let mut crc = i as u8;
+      for _ in 0..8 {
+        crc = if crc & 0x80 != 0 { (crc << 1) ^ polynomial } else { crc << 1 };
+      }
+      *entry = crc;
+    }
+    Crc8Checksum { from, to, result, init: 0, final_xor: 0, table, profile: Crc8Profile::Unspecified }
+  }
+  // struct cgw_csum_crc8
+  fn encode(&self) -> Vec<u8> {
+    let mut buf = vec![0u8; CGW_CS_CRC8_LEN];
+    buf[..5].copy_from_slice(&[self.from as u8, self.to as u8, self.result as u8, self.init, self.final_xor]);
+    buf[5..261].copy_from_slice(&self.table);
+    buf[261] = match self.profile {
+      Crc8Profile::Unspecified => CGW_CRC8PRF_UNSPEC,
+      Crc8Profile::Fixed(byte) => {
+        buf[262] = byte;
+        CGW_CRC8PRF_1U8
+      }
+      Crc8Profile::Indexed(bytes) => {
+        buf[262..278].copy_from_slice(&bytes);
+        CGW_CRC8PRF_16U8
+      }
+      Crc8Profile::SffIdXor => CGW_CRC8PRF_SFFID_XOR,
+    };
+    buf
+  }
+  fn decode(buf: &[u8]) -> Option<Crc8Checksum> {
+    if buf.len() < CGW_CS_CRC8_LEN {
+      return None;
+    }
+    let mut table = [0u8; 256];
+    table.copy_from_slice(&buf[5..261]);
+    let mut indexed = [0u8; 16];
+    indexed.copy_from_slice(&buf[262..278]);
+    let profile = match buf[261] {
+      CGW_CRC8PRF_1U8 => Crc8Profile::Fixed(buf[262]),
+      CGW_CRC8PRF_16U8 => Crc8Profile::Indexed(indexed),
+      CGW_CRC8PRF_SFFID_XOR => Crc8Profile::SffIdXor,
+      _ => Crc8Profile::Unspecified,
+    };
+    Some(Crc8Checksum {
+      from: buf[0] as i8,
+      to: buf[1] as i8,
+      result: buf[2] as i8,
+      init: buf[3],
+      final_xor: buf[4],
+      table,
+      profile,
+    })
+  }
+}
+
+/// Routing rule of the CAN gateway.
+///
+/// Modifications are applied in the order AND, OR, XOR, SET, then the checksums are updated.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GwRule {
+  /// index of the interface frames are received from
+  pub src_ifindex: i32,
+  /// index of the interface frames are sent to
+  pub dst_ifindex: i32,
+  /// forward CAN FD frames instead of classic CAN frames
+  pub fd: bool,
+  /// forwarded frames are also received by local sockets on the destination interface
+  pub echo: bool,
+  /// keep the timestamp of the received frame
+  pub src_tstamp: bool,
+  /// allow sending back to the source interface
+  pub iif_tx_ok: bool,
+  /// only forward frames passing the filter
+  pub filter: Option<Filter>,
+  /// AND modification
+  pub and: Option<GwMod>,
+  /// OR modification
+  pub or: Option<GwMod>,
+  /// XOR modification
+  pub xor: Option<GwMod>,
+  /// SET modification
+  pub set: Option<GwMod>,
+  /// XOR checksum update
+  pub xor_checksum: Option<XorChecksum>,
+  /// CRC8 checksum update
+  pub crc8_checksum: Option<Crc8Checksum>,
+  /// maximum number of gateway hops of a frame
+  pub hop_limit: Option<u8>,
+  /// identifies the rule for removal instead of comparing the modifications
+  pub uid: Option<u32>,
+}
+
+/// Routing rule with its counters, see `GwRule::list`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GwRuleStats {
+  /// the rule
+  pub rule: GwRule,
+  /// number of forwarded frames
+  pub handled: u32,
+  /// number of frames which could not be forwarded
+  pub dropped: u32,
+  /// number of frames dropped because of the hop limit
+  pub deleted: u32,
+}
+
+impl GwRule {
+  /// Creates a rule which forwards all classic CAN frames from `src` to `dst` unmodified.
+  pub fn new(src: &str, dst: &str) -> io::Result<GwRule> {
+    Ok(GwRule {
+      src_ifindex: crate::ifindex(src)?,
+      dst_ifindex: crate::ifindex(dst)?,
+      fd: false,
+      echo: false,
+      src_tstamp: false,
+      iif_tx_ok: false,
+      filter: None,
+      and: None,
+      or: None,
+      xor: None,
+      set: None,
+      xor_checksum: None,
+      crc8_checksum: None,
+      hop_limit: None,
+      uid: None,
+    })
+  }
+  /// Adds the rule to the gateway.
+  pub fn add(&self) -> io::Result<()> {
+    Netlink::open()?.request(&mut self.encode(RTM_NEWROUTE)?)?;
+    Ok(())
+  }
+  /// Removes the rule from the gateway. Without `uid` all settings have to match the added rule.
+  pub fn remove(&self) -> io::Result<()> {
+    Netlink::open()?.request(&mut self.encode(RTM_DELROUTE)?)?;
+    Ok(())
+  }
+  /// Removes all rules from the gateway.
+  pub fn flush() -> io::Result<()> {
+    let mut msg = NlMsg::new(RTM_DELROUTE, 0);
+    msg.header(&RtCanMsg { can_family: AF_CAN as u8, gwtype: CGW_TYPE_CAN_CAN, flags: 0 })
+       .attr_u32(CGW_SRC_IF, 0)
+       .attr_u32(CGW_DST_IF, 0);
+    Netlink::open()?.request(&mut msg)?;
+    Ok(())
+  }
+  /// Lists all rules of the gateway with their counters.
+  pub fn list() -> io::Result<Vec<GwRuleStats>> {
+    let mut msg = NlMsg::new(RTM_GETROUTE, NLM_F_DUMP);
+    msg.header(&RtCanMsg { can_family: AF_CAN as u8, ..Default::default() });
+    Ok(Netlink::open()?.request(&mut msg)?.iter().filter_map(|reply| GwRule::decode(reply)).collect())
+  }
+  fn encode(&self, ty: u16) -> io::Result<NlMsg> {
+    let mut flags = 0;
+    if self.echo {
+      flags |= CGW_FLAGS_CAN_ECHO;
+    }
+    if self.src_tstamp {
+      flags |= CGW_FLAGS_CAN_SRC_TSTAMP;
+    }
+    if self.iif_tx_ok {
+      flags |= CGW_FLAGS_CAN_IIF_TX_OK;
+    }
+    if self.fd {
+      flags |= CGW_FLAGS_CAN_FD;
+    }
+    let mut msg = NlMsg::new(ty, 0);
+    msg.header(&RtCanMsg { can_family: AF_CAN as u8, gwtype: CGW_TYPE_CAN_CAN, flags });
+    let mods = [(&self.and, CGW_MOD_AND, CGW_FDMOD_AND), (&self.or, CGW_MOD_OR, CGW_FDMOD_OR),
+                (&self.xor, CGW_MOD_XOR, CGW_FDMOD_XOR), (&self.set, CGW_MOD_SET, CGW_FDMOD_SET)];
+    for (m, ty, fd_ty) in mods {
+      if let Some(m) = m {
+        msg.attr(if self.fd { fd_ty } else { ty }, &m.encode(self.fd)?);
+      }
+    }
+    if let Some(uid) = self.uid {
+      msg.attr_u32(CGW_MOD_UID, uid);
+    }
+    if let Some(cs) = &self.xor_checksum {
+      msg.attr(CGW_CS_XOR, &[cs.from as u8, cs.to as u8, cs.result as u8, cs.init]);
+    }
+    if let Some(cs) = &self.crc8_checksum {
+      msg.attr(CGW_CS_CRC8, &cs.encode());
+    }
+    if let Some(filter) = &self.filter {
+      let mut raw = filter.can_id.to_ne_bytes().to_vec();
+      raw.extend_from_slice(&filter.can_mask.to_ne_bytes());
+      msg.attr(CGW_FILTER, &raw);
+    }
+    if let Some(hops) = self.hop_limit {
+      msg.attr(CGW_LIM_HOPS, &[hops]);
+    }
+    msg.attr_u32(CGW_SRC_IF, self.src_ifindex as u32)
+       .attr_u32(CGW_DST_IF, self.dst_ifindex as u32);
+    Ok(msg)
+  }
+  fn decode(reply: &[u8]) -> Option<GwRuleStats> {
+    let hdr: RtCanMsg = netlink::from_bytes(reply);
+    if hdr.gwtype != CGW_TYPE_CAN_CAN {
+      return None;
+    }
+    let fd = hdr.flags & CGW_FLAGS_CAN_FD != 0;
+    let mut stats = GwRuleStats {
+      rule: GwRule {
+        src_ifindex: 0,
+        dst_ifindex: 0,
+        fd,
+        echo: hdr.flags & CGW_FLAGS_CAN_ECHO != 0,
+        src_tstamp: hdr.flags & CGW_FLAGS_CAN_SRC_TSTAMP != 0,
+        iif_tx_ok: hdr.flags & CGW_FLAGS_CAN_IIF_TX_OK != 0,
+        filter: None,
+        and: None,
+        or: None,
+        xor: None,
+        set: None,
+        xor_checksum: None,
+        crc8_checksum: None,
+        hop_limit: None,
+        uid: None,
+      },
+      handled: 0,
+      dropped: 0,
+      deleted: 0,
+    };
+    let rule = &mut stats.rule;
+    for (ty, data) in netlink::attrs(reply.get(mem::size_of::<RtCanMsg>()..)?) {
+      match ty {
+        CGW_MOD_AND | CGW_FDMOD_AND => rule.and = GwMod::decode(data, fd),
+        CGW_MOD_OR | CGW_FDMOD_OR => rule.or = GwMod::decode(data, fd),
+        CGW_MOD_XOR | CGW_FDMOD_XOR => rule.xor = GwMod::decode(data, fd),
+        CGW_MOD_SET | CGW_FDMOD_SET => rule.set = GwMod::decode(data, fd),
+        CGW_CS_XOR if data.len() >= 4 => {
+          rule.xor_checksum = Some(XorChecksum { from: data[0] as i8, to: data[1] as i8, result: data[2] as i8, init: data[3] });
+        }
+        CGW_CS_CRC8 => rule.crc8_checksum = Crc8Checksum::decode(data),
+        CGW_FILTER if data.len() >= 8 => {
+          rule.filter = Some(Filter::new(netlink::attr_u32(data)?, netlink::attr_u32(&data[4..])?));
+        }
+        CGW_LIM_HOPS => rule.hop_limit = data.first().copied(),
+        CGW_MOD_UID => rule.uid = netlink::attr_u32(data),
+        CGW_SRC_IF => rule.src_ifindex = netlink::attr_u32(data)? as i32,
+        CGW_DST_IF => rule.dst_ifindex = netlink::attr_u32(data)? as i32,
+        CGW_HANDLED => stats.handled = netlink::attr_u32(data)?,
+        CGW_DROPPED => stats.dropped = netlink::attr_u32(data)?,
+        CGW_DELETED => stats.deleted = netlink::attr_u32(data)?,
+        _ => {
+        }
+      }
+    }
+    Some(stats)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  #[test]
+  fn rule_round_trip() {
+    let mut crc8 = Crc8Checksum::new(0, 6, 7, 0x1d);
+    crc8.profile = Crc8Profile::Fixed(0x42);
+    let rule = GwRule {
+      src_ifindex: 3,
+      dst_ifindex: 4,
+      fd: false,
+      echo: true,
+      src_tstamp: false,
+      iif_tx_ok: false,
+      filter: Some(Filter::new(0x123, crate::SFF_MASK)),
+      and: Some(GwMod { data: Some(vec![0xff; 8]), ..Default::default() }),
+      or: None,
+      xor: None,
+      set: Some(GwMod { can_id: Some(0x321), len: Some(8), ..Default::default() }),
+      xor_checksum: None,
+      crc8_checksum: Some(crc8),
+      hop_limit: Some(2),
+      uid: None,
+    };
+    let bytes = rule.encode(RTM_NEWROUTE).unwrap().bytes().to_vec();
+    let decoded = GwRule::decode(&bytes[16..]).unwrap();
+    assert_eq!(decoded.rule, rule);
+    // SAE J1850 polynomial
+    assert_eq!(crc8.table[1], 0x1d);
+    assert!(GwRule { set: Some(GwMod { flags: Some(1), ..Default::default() }), ..rule }.encode(RTM_NEWROUTE).is_err());
+  }
+  #[test]
+  fn fd_mod_layout() {
+    let m = GwMod { can_id: Some(0x80000123), data: Some(vec![1; 64]), ..Default::default() };
+    let raw = m.encode(true).unwrap();
+    assert_eq!(raw.len(), CGW_FDMODATTR_LEN);
+    assert_eq!(raw[libc::CANFD_MTU], CGW_MOD_ID | CGW_MOD_DATA);
+    assert_eq!(GwMod::decode(&raw, true), Some(m));
+  }
+}
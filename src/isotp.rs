@@ -0,0 +1,205 @@
+//! ISO 15765-2 transport protocol (`CAN_ISOTP`) sockets.
+//!
+//! The kernel segments the PDUs into single, first and consecutive frames and
+//! handles the flow control, so whole PDUs are sent and received.
+
+use std::io;
+use std::mem;
+use std::os::raw::{c_int, c_void};
+
+use chrono::Duration;
+
+use crate::socket_fd::SocketFd;
+use crate::{Id, Msg, AF_CAN};
+
+// Socket options and flags stolen from linux/can/isotp.h
+const SOL_CAN_ISOTP: c_int = 100 + libc::CAN_ISOTP;
+const CAN_ISOTP_OPTS: c_int = 1;
+const CAN_ISOTP_RECV_FC: c_int = 2;
+const CAN_ISOTP_LL_OPTS: c_int = 5;
+
+const CAN_ISOTP_LISTEN_MODE: u32 = 0x0001;
+const CAN_ISOTP_EXTEND_ADDR: u32 = 0x0002;
+const CAN_ISOTP_TX_PADDING: u32 = 0x0004;
+const CAN_ISOTP_RX_PADDING: u32 = 0x0008;
+const CAN_ISOTP_RX_EXT_ADDR: u32 = 0x0200;
+
+/// `struct can_isotp_options`
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct CanIsotpOptions {
+  flags: u32,
+  frame_txtime: u32,
+  ext_address: u8,
+  txpad_content: u8,
+  rxpad_content: u8,
+  rx_ext_address: u8,
+}
+
+/// `struct can_isotp_fc_options`
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct CanIsotpFcOptions {
+  bs: u8,
+  stmin: u8,
+  wftmax: u8,
+}
+
+/// `struct can_isotp_ll_options`
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct CanIsotpLlOptions {
+  mtu: u8,
+  tx_dl: u8,
+  tx_flags: u8,
+}
+
+/// Options of an ISO-TP socket, see `IsoTp::open`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IsoTpOptions {
+  /// pad sent frames to the full length with this byte
+  pub tx_padding: Option<u8>,
+  /// expect received frames to be padded with this byte
+  pub rx_padding: Option<u8>,
+  /// extended addressing, the first data byte of sent frames holds this address
+  pub ext_address: Option<u8>,
+  /// extended addressing for received frames, defaults to `ext_address`
+  pub rx_ext_address: Option<u8>,
+  /// number of consecutive frames the sender may send before waiting for the next
+  /// flow control frame, zero disables the flow control after the first frame
+  pub block_size: u8,
+  /// minimum time between consecutive frames requested from the sender,
+  /// in steps of 100 µs up to 900 µs and of 1 ms up to 127 ms
+  pub stmin: Duration,
+  /// maximum number of wait frames sent, zero disables them
+  pub wftmax: u8,
+  /// only receive, never send flow control frames
+  pub listen_mode: bool,
+  /// use CAN FD frames with this data length (8, 12, 16, 20, 24, 32, 48 or 64)
+  pub fd: Option<u8>,
+  /// send CAN FD frames with bit rate switch
+  pub brs: bool,
+}
+impl Default for IsoTpOptions {
+  fn default() -> IsoTpOptions {
+    IsoTpOptions {
+      tx_padding: None,
+      rx_padding: None,
+      ext_address: None,
+      rx_ext_address: None,
+      block_size: 0,
+      stmin: Duration::zero(),
+      wftmax: 0,
+      listen_mode: false,
+      fd: None,
+      brs: false,
+    }
+  }
+}
+
+/// ISO-TP socket for one pair of TX and RX identifiers.
+pub struct IsoTp {
+  pub(crate) fd: SocketFd,
+}
+impl IsoTp {
+  /// Open an ISO-TP socket on the CAN device with the netdev name. PDUs are sent with
+  /// `tx_id` and received with `rx_id`, flow control frames the other way around.
+  pub fn open(ifname: &str, tx_id: Id, rx_id: Id, options: &IsoTpOptions) -> io::Result<IsoTp> {
+    let ifindex = crate::ifindex(ifname)?;
+    let mut opts = CanIsotpOptions::default();
+    if options.listen_mode {
+      opts.flags |= CAN_ISOTP_LISTEN_MODE;
+    }
+    if let Some(content) = options.tx_padding {
+      opts.flags |= CAN_ISOTP_TX_PADDING;
+      opts.txpad_content = content;
+    }
+    if let Some(content) = options.rx_padding {
+      opts.flags |= CAN_ISOTP_RX_PADDING;
+      opts.rxpad_content = content;
+    }
+    if let Some(address) = options.ext_address {
+      opts.flags |= CAN_ISOTP_EXTEND_ADDR;
+      opts.ext_address = address;
+    }
+    if let Some(address) = options.rx_ext_address {
+      opts.flags |= CAN_ISOTP_EXTEND_ADDR | CAN_ISOTP_RX_EXT_ADDR;
+      opts.rx_ext_address = address;
+    }
+    let fc = CanIsotpFcOptions {
+      bs: options.block_size,
+      stmin: encode_stmin(options.stmin)?,
+      wftmax: options.wftmax,
+    };
+    let isotp = IsoTp { fd: SocketFd::open(libc::SOCK_DGRAM, libc::CAN_ISOTP)? };
+    unsafe {
+      isotp.set_opt(CAN_ISOTP_OPTS, &opts)?;
+      isotp.set_opt(CAN_ISOTP_RECV_FC, &fc)?;
+      if let Some(tx_dl) = options.fd {
+        let ll = CanIsotpLlOptions {
+          mtu: libc::CANFD_MTU as u8,
+          tx_dl,
+          tx_flags: if options.brs { crate::CANFD_BRS } else { 0 },
+        };
+        isotp.set_opt(CAN_ISOTP_LL_OPTS, &ll)?;
+      }
+      let mut addr: libc::sockaddr_can = mem::zeroed();
+      addr.can_family = AF_CAN as u16;
+      addr.can_ifindex = ifindex;
+      addr.can_addr.tp.tx_id = tx_id.can_id();
+      addr.can_addr.tp.rx_id = rx_id.can_id();
+      if libc::bind(isotp.fd.raw(), &addr as *const libc::sockaddr_can as *const libc::sockaddr, mem::size_of::<libc::sockaddr_can>() as u32) != 0 {
+        return Err(io::Error::last_os_error());
+      }
+      Ok(isotp)
+    }
+  }
+  /// Sends a PDU. Blocks until the transmission started.
+  pub fn send(&self, data: &[u8]) -> io::Result<()> {
+    let nbytes = unsafe { libc::write(self.fd.raw(), data.as_ptr() as *const c_void, data.len()) };
+    if nbytes < 0 {
+      return Err(io::Error::last_os_error());
+    }
+    if nbytes as usize != data.len() {
+      return Err(io::Error::new(io::ErrorKind::WriteZero, "PDU was only partially written"));
+    }
+    Ok(())
+  }
+  /// Receives the next PDU. Get it with `Msg::pdu`. PDUs larger than 64 KiB fail with `InvalidData`.
+  pub fn recv(&self, msg: &mut Msg) -> io::Result<()> {
+    self.fd.recv_pdu(msg, libc::CAN_ISOTP)
+  }
+  fn set_opt<O>(&self, name: c_int, value: &O) -> io::Result<()> {
+    self.fd.set_opt(SOL_CAN_ISOTP, name, value)
+  }
+}
+
+// STmin is 0x00 - 0x7f in ms or 0xf1 - 0xf9 in 100 µs steps
+fn encode_stmin(stmin: Duration) -> io::Result<u8> {
+  let us = stmin.num_microseconds().unwrap_or(i64::MAX);
+  match us {
+    0 => Ok(0),
+    1..=900 if us % 100 == 0 => Ok(0xf0 + (us / 100) as u8),
+    _ if us % 1000 == 0 && (1000..=127_000).contains(&us) => Ok((us / 1000) as u8),
+    _ => Err(io::Error::new(io::ErrorKind::InvalidInput, "STmin must be 100 - 900 µs or 0 - 127 ms")),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  #[test]
+  fn option_layout() {
+    assert_eq!(mem::size_of::<CanIsotpOptions>(), 12);
+    assert_eq!(mem::size_of::<CanIsotpFcOptions>(), 3);
+    assert_eq!(mem::size_of::<CanIsotpLlOptions>(), 3);
+  }
+  #[test]
+  fn stmin_encoding() {
+    assert_eq!(encode_stmin(Duration::zero()).unwrap(), 0);
+    assert_eq!(encode_stmin(Duration::microseconds(300)).unwrap(), 0xf3);
+    assert_eq!(encode_stmin(Duration::milliseconds(127)).unwrap(), 0x7f);
+    assert!(encode_stmin(Duration::microseconds(1500)).is_err());
+    assert!(encode_stmin(Duration::milliseconds(128)).is_err());
+  }
+}
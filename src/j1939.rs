@@ -0,0 +1,230 @@
+//! SAE J1939 (`CAN_J1939`) sockets.
+//!
+//! The kernel handles the transport protocols for messages longer than 8 bytes and keeps
+//! track of the address claims on the bus, so ECUs can be addressed by their NAME.
+
+use std::io;
+use std::mem;
+use std::os::raw::{c_int, c_void};
+use std::ptr;
+
+use crate::socket_fd::SocketFd;
+use crate::{Msg, AF_CAN};
+
+// Socket options and control messages stolen from linux/can/j1939.h
+const SOL_CAN_J1939: c_int = 100 + libc::CAN_J1939;
+const SO_J1939_PROMISC: c_int = 2;
+const SO_J1939_SEND_PRIO: c_int = 3;
+const SCM_J1939_DEST_ADDR: c_int = 1;
+const SCM_J1939_DEST_NAME: c_int = 2;
+const SCM_J1939_PRIO: c_int = 3;
+
+/// no NAME
+pub const J1939_NO_NAME: u64 = 0;
+/// no PGN
+pub const J1939_NO_PGN: u32 = 0x40000;
+/// no address, also the broadcast address
+pub const J1939_NO_ADDR: u8 = 0xff;
+/// address of an ECU which has not claimed an address yet
+pub const J1939_IDLE_ADDR: u8 = 0xfe;
+/// PGN of the address claimed message
+pub const J1939_PGN_ADDRESS_CLAIMED: u32 = 0x0ee00;
+
+/// Address of a J1939 socket or of the peer of a message.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct J1939Addr {
+  /// 64 bit NAME of the ECU, `J1939_NO_NAME` if unknown or unused
+  pub name: u64,
+  /// parameter group number, `J1939_NO_PGN` to bind to all PGNs
+  pub pgn: u32,
+  /// 8 bit address, `J1939_NO_ADDR` if unknown or for broadcasts
+  pub addr: u8,
+}
+impl J1939Addr {
+  /// Creates an address from the 8 bit address only.
+  pub fn new(pgn: u32, addr: u8) -> J1939Addr {
+    J1939Addr { name: J1939_NO_NAME, pgn, addr }
+  }
+  /// Creates an address from the NAME, the kernel looks up the claimed address.
+  pub fn with_name(name: u64, pgn: u32) -> J1939Addr {
+    J1939Addr { name, pgn, addr: J1939_NO_ADDR }
+  }
+}
+impl Default for J1939Addr {
+  fn default() -> J1939Addr {
+    J1939Addr { name: J1939_NO_NAME, pgn: J1939_NO_PGN, addr: J1939_NO_ADDR }
+  }
+}
+
+/// Addresses and priority of a message received from a J1939 socket, see `Msg::j1939`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct J1939Header {
+  /// parameter group number
+  pub pgn: u32,
+  /// priority, 0 is the highest
+  pub priority: u8,
+  /// source address
+  pub src_addr: u8,
+  /// NAME of the source, if the kernel saw it claim the address
+  pub src_name: Option<u64>,
+  /// destination address, `J1939_NO_ADDR` for broadcasts
+  pub dst_addr: u8,
+  /// NAME of the destination, if the kernel saw it claim the address
+  pub dst_name: Option<u64>,
+}
+
+/// Address claimed by an ECU, see `Msg::address_claim`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AddressClaim {
+  /// NAME of the ECU
+  pub name: u64,
+  /// claimed address, `J1939_IDLE_ADDR` if the ECU could not claim an address
+  pub addr: u8,
+}
+
+/// J1939 socket.
+pub struct J1939 {
+  pub(crate) fd: SocketFd,
+}
+impl J1939 {
+  /// Open a J1939 socket on the CAN device with the netdev name and bind it to the local address.
+  /// If a NAME is given, the kernel sends from the address claimed for it, see `J1939::claim_address`.
+  /// A PGN other than `J1939_NO_PGN` only receives messages with this PGN.
+  pub fn open(ifname: &str, local: &J1939Addr) -> io::Result<J1939> {
+    let ifindex = crate::ifindex(ifname)?;
+    let j1939 = J1939 { fd: SocketFd::open(libc::SOCK_DGRAM, libc::CAN_J1939)? };
+    unsafe {
+      let addr = sockaddr(ifindex, local);
+      if libc::bind(j1939.fd.raw(), &addr as *const libc::sockaddr_can as *const libc::sockaddr, mem::size_of::<libc::sockaddr_can>() as u32) != 0 {
+        return Err(io::Error::last_os_error());
+      }
+      Ok(j1939)
+    }
+  }
+  /// Sends a message to the destination, `dst.pgn` is the PGN of the message.
+  /// Messages longer than 8 bytes are sent with the transport protocol.
+  pub fn send_to(&self, data: &[u8], dst: &J1939Addr) -> io::Result<()> {
+    unsafe {
+      let addr = sockaddr(0, dst);
+      let nbytes = libc::sendto(self.fd.raw(), data.as_ptr() as *const c_void, data.len(), 0,
+                                &addr as *const libc::sockaddr_can as *const libc::sockaddr, mem::size_of::<libc::sockaddr_can>() as u32);
+      if nbytes < 0 {
+        return Err(io::Error::last_os_error());
+      }
+      if nbytes as usize != data.len() {
+        return Err(io::Error::new(io::ErrorKind::WriteZero, "message was only partially written"));
+      }
+    }
+    Ok(())
+  }
+  /// Broadcasts the address claimed message with the NAME and address the socket is bound to.
+  /// Requires `J1939::set_broadcast`. The kernel accepts messages from the NAME after 250 ms,
+  /// if no other ECU with a higher priority NAME claimed the address meanwhile.
+  pub fn claim_address(&self) -> io::Result<()> {
+    let local = self.local_addr()?;
+    self.send_to(&local.name.to_le_bytes(), &J1939Addr::new(J1939_PGN_ADDRESS_CLAIMED, J1939_NO_ADDR))
+  }
+  /// Get the address the socket is bound to.
+  pub fn local_addr(&self) -> io::Result<J1939Addr> {
+    unsafe {
+      let mut addr: libc::sockaddr_can = mem::zeroed();
+      let mut len = mem::size_of::<libc::sockaddr_can>() as u32;
+      if libc::getsockname(self.fd.raw(), &mut addr as *mut libc::sockaddr_can as *mut libc::sockaddr, &mut len) != 0 {
+        return Err(io::Error::last_os_error());
+      }
+      Ok(J1939Addr { name: addr.can_addr.j1939.name, pgn: addr.can_addr.j1939.pgn, addr: addr.can_addr.j1939.addr })
+    }
+  }
+  /// Allow sending to and receiving from the broadcast address.
+  pub fn set_broadcast(&self, enable: bool) -> io::Result<()> {
+    self.fd.set_int(libc::SOL_SOCKET, libc::SO_BROADCAST, enable as c_int)
+  }
+  /// Receive all messages on the bus, not only those addressed to the socket.
+  pub fn set_promisc(&self, enable: bool) -> io::Result<()> {
+    self.fd.set_int(SOL_CAN_J1939, SO_J1939_PROMISC, enable as c_int)
+  }
+  /// Set the priority (0 - 7, 0 is the highest) of sent messages. Defaults to 6.
+  pub fn set_send_priority(&self, priority: u8) -> io::Result<()> {
+    if priority > 7 {
+      return Err(io::Error::new(io::ErrorKind::InvalidInput, "J1939 priorities range from 0 to 7"));
+    }
+    self.fd.set_int(SOL_CAN_J1939, SO_J1939_SEND_PRIO, priority as c_int)
+  }
+  /// Receives the next message. Get it with `Msg::pdu` and its addresses with `Msg::j1939`.
+  /// Messages larger than 64 KiB, which the extended transport protocol allows, fail with `InvalidData`.
+  pub fn recv(&self, msg: &mut Msg) -> io::Result<()> {
+    self.fd.recv_pdu(msg, libc::CAN_J1939)
+  }
+}
+
+fn sockaddr(ifindex: c_int, addr: &J1939Addr) -> libc::sockaddr_can {
+  let mut sa: libc::sockaddr_can = unsafe { mem::zeroed() };
+  sa.can_family = AF_CAN as u16;
+  sa.can_ifindex = ifindex;
+  sa.can_addr.j1939.name = addr.name;
+  sa.can_addr.j1939.pgn = addr.pgn;
+  sa.can_addr.j1939.addr = addr.addr;
+  sa
+}
+
+/// Reads the source address and the control messages of a message received by `J1939::recv`.
+pub(crate) fn header(msg: &libc::msghdr, src: &libc::sockaddr_can) -> J1939Header {
+  let src = unsafe { src.can_addr.j1939 };
+  let mut header = J1939Header {
+    pgn: src.pgn,
+    priority: 0,
+    src_addr: src.addr,
+    src_name: if src.name != J1939_NO_NAME { Some(src.name) } else { None },
+    dst_addr: J1939_NO_ADDR,
+    dst_name: None,
+  };
+  unsafe {
+    let mut cmsg = libc::CMSG_FIRSTHDR(msg);
+    while !cmsg.is_null() {
+      if (*cmsg).cmsg_level == SOL_CAN_J1939 {
+        match (*cmsg).cmsg_type {
+          SCM_J1939_DEST_ADDR => header.dst_addr = *libc::CMSG_DATA(cmsg),
+          SCM_J1939_DEST_NAME => header.dst_name = Some(ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const u64)),
+          SCM_J1939_PRIO => header.priority = *libc::CMSG_DATA(cmsg),
+          _ => {
+          }
+        };
+      }
+      cmsg = libc::CMSG_NXTHDR(msg, cmsg);
+    }
+  }
+  header
+}
+
+/// Decodes an address claimed message.
+pub(crate) fn decode_address_claim(header: &J1939Header, data: &[u8]) -> Option<AddressClaim> {
+  if header.pgn != J1939_PGN_ADDRESS_CLAIMED || data.len() != 8 {
+    return None;
+  }
+  let mut name = [0u8; 8];
+  name.copy_from_slice(data);
+  Some(AddressClaim { name: u64::from_le_bytes(name), addr: header.src_addr })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  #[test]
+  fn address_claim_decoding() {
+    let header = J1939Header {
+      pgn: J1939_PGN_ADDRESS_CLAIMED,
+      priority: 6,
+      src_addr: 0x20,
+      src_name: None,
+      dst_addr: J1939_NO_ADDR,
+      dst_name: None,
+    };
+    let name: u64 = 0x8000_2000_0120_0001;
+    assert_eq!(decode_address_claim(&header, &name.to_le_bytes()), Some(AddressClaim { name, addr: 0x20 }));
+    assert_eq!(decode_address_claim(&J1939Header { pgn: 0xfeca, ..header }, &name.to_le_bytes()), None);
+    let sa = sockaddr(3, &J1939Addr::new(0xfeca, 0x20));
+    let hdr: libc::msghdr = unsafe { mem::zeroed() };
+    let decoded = super::header(&hdr, &sa);
+    assert_eq!((decoded.pgn, decoded.src_addr, decoded.src_name), (0xfeca, 0x20, None));
+  }
+}
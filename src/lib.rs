@@ -2,13 +2,38 @@
 //! # Features
 //! * Receive can frames
 //! * Accurate timestamps (timestamps also support multi threading in contrast to receiving the TIMESTAMP via an ioctl call, which does not support mt)
+//! * Software and hardware timestamps via `SO_TIMESTAMPING` (`Can::set_timestamping`, `Msg::timestamps`)
+//! * Detect frames dropped by the kernel (`Can::set_rxq_overflow`, `Msg::drops`, `CanGroup::dropped`)
 //! * epoll-support (allows to wait on multiple CAN devices in the same thread)
-//! * Send CAN frames (not implemented yet)
-//! * Filter CAN frames (not implemented yet)
+//! * Receive from all CAN devices with a single socket (`Can::open_all`)
+//! * Send CAN and CAN FD frames
+//! * Owned frame types (`CanFrame`, `CanFdFrame`) for building and decoding frames
+//! * Filter CAN frames in the kernel (`Can::set_filters`)
+//! * Receive and decode error frames (`Can::set_error_filter`, `Msg::error_frame`)
+//! * TX confirmation by receiving own frames (`Can::set_recv_own_msgs`, `Msg::is_tx_confirmation`)
+//! * Non-blocking mode, receive/send timeouts and buffer sizes on single sockets
+//! * Batched receiving and sending (`MsgBatch`, `Can::recv_batch`, `Can::send_batch`)
+//! * CAN XL frames (`Can::set_xl_frames`, `CanXlFrame`)
+//! * Cyclic transmission by the kernel with broadcast manager sockets (`Bcm`)
+//! * Content filtering and receive timeouts by the broadcast manager (`Bcm::rx_setup`, `BcmEvent`)
+//! * ISO-TP sockets sending and receiving whole PDUs (`IsoTp`, `Msg::pdu`)
+//! * J1939 sockets with addressing by NAME and address claiming (`J1939`, `Msg::j1939`)
+//! * Interface configuration via netlink: up/down, bitrates, control modes and restarts (`CanInterface`)
+//! * Controller state, bus error counters and statistics via netlink (`CanInterface::status`)
+//! * Creating and deleting vcan interfaces and vxcan pairs (`CanInterface::create_vcan`, `CanInterface::create_vxcan`)
+//! * Routing rules of the kernel CAN gateway with frame modifications and checksums (`GwRule`)
+//! * Discovery of CAN interfaces with their type, MTU and state (`CanInterface::list`)
+//! * tokio support with `Stream` and `Sink` of frames (`AsyncCan`, feature `tokio`)
+//! * async-io support for smol and async-std (`AsyncIoCan`, `AsyncIoCanGroup`, feature `async-io`)
+//! * Raw fd traits for custom event loops and mio support (feature `mio`)
+//! * Adding, removing and replacing `CanGroup` members at runtime (`CanHandle`)
 //! # Usage example
-//! ```
-//! #[cfg(test)]
-//! fn on_recv(msg: &Box<Msg>, _user_data: &u64) {
+//! ```no_run
+//! use chrono::Duration;
+//! use socketcan2::{Can, CanGroup, Msg};
+//!
+//! fn on_recv(msg: &Msg, received: &mut u64) {
+//!   *received += 1;
 //!   println!("timestamp: {:?}", msg.timestamp());
 //!   print!("received CAN frame (id: {}): ", msg.can_id());
 //!   for i in 0..msg.len() {
@@ -16,47 +41,83 @@
 //!   }
 //!   println!("");
 //! }
-//! 
-//! #[cfg(test)]
-//! mod tests {
-//!   use super::*;
-//!   #[test]
-//!   fn it_works() {
-//!     let can = Can::open("vcan0").unwrap();
-//!     let mut cg = CanGroup::<u64>::new();
-//!     cg.add(can, 0).unwrap();
-//!     match cg.next(Duration::milliseconds(-1), on_recv) {
-//!       Ok(no_timeout) => if !no_timeout { panic!("timeout"); },
-//!       Err(_) => panic!("error"),
-//!     }
-//!   }
+//!
+//! let can = Can::open("vcan0").unwrap();
+//! can.send(0x123, &[0xde, 0xad, 0xbe, 0xef]).unwrap();
+//! let mut cg = CanGroup::<u64>::new();
+//! cg.add(can, 0).unwrap();
+//! match cg.next(Duration::milliseconds(-1), on_recv) {
+//!   Ok(no_timeout) => if !no_timeout { panic!("timeout"); },
+//!   Err(_) => panic!("error"),
 //! }
+//! // closures can capture state of the caller
+//! let mut ids = Vec::new();
+//! cg.next(Duration::milliseconds(-1), |msg, _| ids.push(msg.can_id())).unwrap();
 //! ```
 
 use std::mem;
+use std::ptr;
 use std::io;
+use std::collections::BTreeMap;
 use std::ops::Index;
 use std::{os::raw::{c_char, c_int, c_void}};
 
 use chrono::Duration;
 
-#[allow(invalid_value)]
+#[cfg(feature = "async-io")]
+mod async_io_can;
+#[cfg(feature = "tokio")]
+mod async_tokio;
+mod bcm;
+mod error_frame;
+mod frame;
+mod gw;
+mod isotp;
+mod j1939;
+mod link;
+mod netlink;
+mod socket_fd;
+#[cfg(feature = "async-io")]
+pub use async_io_can::{AsyncIoCan, AsyncIoCanGroup};
+#[cfg(feature = "tokio")]
+pub use async_tokio::AsyncCan;
+pub use bcm::{Bcm, BcmEvent, RxJob, TxJob};
+pub use error_frame::{
+  ControllerError, ErrorCounters, ErrorFrame, ProtocolError, ProtocolLocation, TransceiverError, WireStatus,
+  ERR_ACK, ERR_BUSERROR, ERR_BUSOFF, ERR_CNT, ERR_CRTL, ERR_LOSTARB, ERR_PROT, ERR_RESTARTED, ERR_TRX, ERR_TX_TIMEOUT,
+};
+pub use frame::{CanFdFrame, CanFrame, CanXlFrame, Frame, Id, CANFD_BRS, CANFD_ESI, CANXL_SEC, CANXL_XLF};
+use frame::RawFrame;
+pub use gw::{Crc8Checksum, Crc8Profile, GwMod, GwRule, GwRuleStats, XorChecksum};
+pub use isotp::{IsoTp, IsoTpOptions};
+pub use j1939::{
+  AddressClaim, J1939, J1939Addr, J1939Header,
+  J1939_IDLE_ADDR, J1939_NO_ADDR, J1939_NO_NAME, J1939_NO_PGN, J1939_PGN_ADDRESS_CLAIMED,
+};
+pub use link::{
+  BerrCounter, CanConfig, CanDeviceStats, CanInterface, CanInterfaceInfo, CanState, CanStatus, LinkStats, OperState,
+  CTRLMODE_3_SAMPLES, CTRLMODE_BERR_REPORTING, CTRLMODE_FD, CTRLMODE_FD_NON_ISO, CTRLMODE_LISTENONLY, CTRLMODE_LOOPBACK,
+  CTRLMODE_ONE_SHOT, CTRLMODE_PRESUME_ACK,
+};
 
 // Constants stolen from C headers
 const AF_CAN: c_int = 29;
 const PF_CAN: c_int = 29;
+// CAN XL socket options from linux/can/raw.h
+const CAN_RAW_XL_FRAMES: c_int = 7;
+const CAN_RAW_XL_VCID_OPTS: c_int = 8;
 // Unused yet
 // const CAN_RAW: c_int = 1;
 // const SOL_CAN_BASE: c_int = 100;
 // const SOL_CAN_RAW: c_int = SOL_CAN_BASE + CAN_RAW;
-// const CAN_RAW_FILTER: c_int = 1;
-// const CAN_RAW_ERR_FILTER: c_int = 2;
-// const CAN_RAW_LOOPBACK: c_int = 3;
-// const CAN_RAW_RECV_OWN_MSGS: c_int = 4;
 // const CAN_RAW_FD_FRAMES: c_int = 5;
-// const CAN_RAW_JOIN_FILTERS: c_int = 6;
 // const SIOCGSTAMP: c_int = 0x8906;
 // const SIOCGSTAMPNS: c_int = 0x8907;
+
+// Size of the receive buffer of ISO-TP and J1939 sockets. ISO-TP PDUs of classic CAN are limited to
+// 4095 bytes, larger ISO-TP PDUs with CAN FD by the `max_pdu_size` parameter of the kernel module.
+const MAX_PDU: usize = 65536;
+
 /// if set, indicate 29 bit extended format
 pub const EFF_FLAG: u32 = 0x80000000;
 /// remote transmission request flag
@@ -73,6 +134,56 @@ pub const ERR_MASK: u32 = 0x1fffffff;
 pub const ERR_MASK_ALL: u32 = ERR_MASK;
 /// an error mask that will cause SocketCAN to silently drop all errors
 pub const ERR_MASK_NONE: u32 = 0;
+/// if set in `Filter::can_id`, the filter matches all frames which do not match the filter
+pub const INV_FILTER: u32 = 0x20000000;
+
+/// Receive filter which is evaluated by the kernel.
+///
+/// A frame passes the filter if `frame_can_id & can_mask == can_id & can_mask`.
+/// The flags `EFF_FLAG` and `RTR_FLAG` can be part of `can_id` and `can_mask`
+/// to distinguish standard/extended and data/remote frames.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Filter {
+  /// identifier to match
+  pub can_id: u32,
+  /// bits of the identifier which have to match
+  pub can_mask: u32,
+}
+impl Filter {
+  /// Creates a filter which passes all frames matching `can_id` under `can_mask`.
+  pub fn new(can_id: u32, can_mask: u32) -> Filter {
+    Filter { can_id, can_mask }
+  }
+  /// Creates a filter which passes all frames not matching `can_id` under `can_mask`.
+  pub fn inverted(can_id: u32, can_mask: u32) -> Filter {
+    Filter { can_id: can_id | INV_FILTER, can_mask }
+  }
+  /// Returns true if the filter is inverted.
+  pub fn is_inverted(&self) -> bool {
+    self.can_id & INV_FILTER != 0
+  }
+}
+
+/// Resolves the netdev name to its interface index.
+fn ifindex(ifname: &str) -> io::Result<c_int> {
+  check_ifname(ifname)?;
+  let cifname = std::ffi::CString::new(ifname).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "interface name contains a NUL byte"))?;
+  unsafe {
+    let ifindex = libc::if_nametoindex(cifname.as_ptr()) as c_int;
+    if ifindex == 0 {
+      return Err(io::Error::last_os_error());
+    }
+    Ok(ifindex)
+  }
+}
+
+/// Interface names are limited to `IF_NAMESIZE` bytes including the terminating NUL.
+fn check_ifname(ifname: &str) -> io::Result<()> {
+  if ifname.is_empty() || ifname.len() >= libc::IF_NAMESIZE {
+    return Err(io::Error::new(io::ErrorKind::InvalidInput, "interface names take 1 to 15 bytes"));
+  }
+  Ok(())
+}
 
 /// CAN socket
 ///
@@ -84,23 +195,24 @@ impl Can
 {
   /// Open CAN device by netdev name.
   pub fn open(ifname: &str) -> Result<Can, io::Error> {
+    Can::open_ifindex(ifindex(ifname)?)
+  }
+  /// Open a CAN socket which receives from all CAN devices (binds to interface index 0).
+  /// `Msg::ifindex` and `Msg::ifname` tell from which device a frame was received.
+  /// Frames can't be sent through such a socket.
+  pub fn open_all() -> Result<Can, io::Error> {
+    Can::open_ifindex(0)
+  }
+  fn open_ifindex(ifindex: c_int) -> Result<Can, io::Error> {
     unsafe {
-      if ifname.len() > 16 {
-        return Err(io::Error::new(io::ErrorKind::Other, "No such device"));
-      }
       let fd = libc::socket(PF_CAN, libc::SOCK_RAW, libc::CAN_RAW);
-      let mut uaddr = mem::MaybeUninit::<libc::sockaddr_can>::uninit();
-      let mut addr = uaddr.as_mut_ptr();
-      (*addr).can_family = AF_CAN as u16;
-      let mut cifname = [0 as c_char; 17];
-      for (i, ch) in ifname.chars().enumerate() {
-        cifname[i] = ch as c_char;
-      }
-      (*addr).can_ifindex = libc::if_nametoindex(&cifname as *const c_char) as i32;
-      if (*addr).can_ifindex == 0 {
+      if fd < 0 {
         return Err(io::Error::last_os_error());
       }
-      let can = Can { fd: fd };
+      let can = Can { fd };
+      let mut addr: libc::sockaddr_can = mem::zeroed();
+      addr.can_family = AF_CAN as u16;
+      addr.can_ifindex = ifindex;
       {
         let timestamp_on: c_int = 1;
         if libc::setsockopt(can.fd, libc::SOL_SOCKET, libc::SO_TIMESTAMP, &timestamp_on as *const c_int as *const c_void, mem::size_of::<c_int>() as u32 + 2) < 0 {
@@ -111,25 +223,367 @@ impl Can
         let opt_on: c_int = 1;
         libc::setsockopt(can.fd, libc::SOL_CAN_RAW, libc::CAN_RAW_FD_FRAMES, &opt_on as *const c_int as *const c_void, mem::size_of::<c_int>() as u32);
       }
-      if libc::bind(can.fd, addr as *const libc::sockaddr_can as *const libc::sockaddr, mem::size_of::<libc::sockaddr_can>() as u32) != 0 {
+      if libc::bind(can.fd, &addr as *const libc::sockaddr_can as *const libc::sockaddr, mem::size_of::<libc::sockaddr_can>() as u32) != 0 {
         return Err(io::Error::last_os_error());
       }
       Ok(can)
     }
   }
+  /// Sends a classic CAN frame (`CAN_MTU` bytes are written to the socket).
+  /// `can_id` is passed to the kernel as is, so `EFF_FLAG` and `RTR_FLAG` may be set.
+  /// At most 8 data bytes are allowed.
+  ///
+  /// If the TX queue of the interface is full, the returned error is the OS error `ENOBUFS`
+  /// (`raw_os_error() == Some(libc::ENOBUFS)`). If the kernel accepted only part of the frame,
+  /// an error of kind `io::ErrorKind::WriteZero` is returned.
+  pub fn send(&self, can_id: u32, data: &[u8]) -> io::Result<()> {
+    if data.len() > libc::CAN_MAX_DLEN {
+      return Err(io::Error::new(io::ErrorKind::InvalidInput, "CAN frames carry at most 8 data bytes"));
+    }
+    unsafe {
+      let mut frame: libc::can_frame = mem::zeroed();
+      frame.can_id = can_id;
+      frame.can_dlc = data.len() as u8;
+      frame.data[..data.len()].copy_from_slice(data);
+      self.write_frame(&frame, libc::CAN_MTU)
+    }
+  }
+  /// Sends a CAN FD frame (`CANFD_MTU` bytes are written to the socket).
+  /// `flags` takes the CAN FD flags (`CANFD_BRS`, `CANFD_ESI`). At most 64 data bytes are allowed.
+  ///
+  /// Errors are reported the same way as in `Can::send`.
+  pub fn send_fd(&self, can_id: u32, flags: u8, data: &[u8]) -> io::Result<()> {
+    if data.len() > libc::CANFD_MAX_DLEN {
+      return Err(io::Error::new(io::ErrorKind::InvalidInput, "CAN FD frames carry at most 64 data bytes"));
+    }
+    unsafe {
+      let mut frame: libc::canfd_frame = mem::zeroed();
+      frame.can_id = can_id;
+      frame.len = data.len() as u8;
+      frame.flags = flags;
+      frame.data[..data.len()].copy_from_slice(data);
+      self.write_frame(&frame, libc::CANFD_MTU)
+    }
+  }
+  /// Sends an owned frame as classic CAN, CAN FD or CAN XL frame depending on its kind.
+  /// CAN XL frames require `Can::set_xl_frames`.
+  pub fn send_frame(&self, frame: &Frame) -> io::Result<()> {
+    let (raw, mtu) = frame.encode();
+    unsafe { self.write_frame(&raw, mtu) }
+  }
+  /// Sends multiple frames with a single `sendmmsg` call.
+  /// Returns the number of frames sent, which is less than `frames.len()` if the TX queue
+  /// of the interface ran full (or the send timeout expired) after sending at least one frame.
+  /// Errors are reported the same way as in `Can::send`.
+  pub fn send_batch(&self, frames: &[Frame]) -> io::Result<usize> {
+    if frames.is_empty() {
+      return Ok(0);
+    }
+    let mut raw: Vec<(RawFrame, usize)> = frames.iter().map(|frame| frame.encode()).collect();
+    let mut iovs: Vec<libc::iovec> = raw.iter_mut()
+      .map(|(frame, mtu)| libc::iovec { iov_base: frame as *mut RawFrame as *mut c_void, iov_len: *mtu })
+      .collect();
+    unsafe {
+      let mut hdrs: Vec<libc::mmsghdr> = iovs.iter_mut().map(|iov| {
+        let mut hdr: libc::mmsghdr = mem::zeroed();
+        hdr.msg_hdr.msg_iov = iov;
+        hdr.msg_hdr.msg_iovlen = 1;
+        hdr
+      }).collect();
+      let sent = libc::sendmmsg(self.fd, hdrs.as_mut_ptr(), hdrs.len() as u32, 0);
+      if sent < 0 {
+        return Err(self.last_error());
+      }
+      for (hdr, (_, mtu)) in hdrs.iter().zip(raw.iter()).take(sent as usize) {
+        if hdr.msg_len as usize != *mtu {
+          return Err(io::Error::new(io::ErrorKind::WriteZero, "CAN frame was only partially written"));
+        }
+      }
+      Ok(sent as usize)
+    }
+  }
+  unsafe fn write_frame<F>(&self, frame: &F, mtu: usize) -> io::Result<()> {
+    let nbytes = libc::write(self.fd, frame as *const F as *const c_void, mtu);
+    if nbytes < 0 {
+      return Err(self.last_error());
+    }
+    if nbytes as usize != mtu {
+      return Err(io::Error::new(io::ErrorKind::WriteZero, "CAN frame was only partially written"));
+    }
+    Ok(())
+  }
+  /// Installs the receive filters, replacing the active ones. Can be called at any time.
+  /// A frame is received if it passes any of the filters (or all of them, see `Can::set_join_filters`).
+  /// An empty list disables the reception of frames, `[Filter::new(0, 0)]` (the default) receives all frames.
+  pub fn set_filters(&self, filters: &[Filter]) -> io::Result<()> {
+    let raw: Vec<libc::can_filter> = filters.iter().map(|f| libc::can_filter { can_id: f.can_id, can_mask: f.can_mask }).collect();
+    unsafe {
+      if libc::setsockopt(self.fd, libc::SOL_CAN_RAW, libc::CAN_RAW_FILTER, raw.as_ptr() as *const c_void, mem::size_of_val(raw.as_slice()) as u32) < 0 {
+        return Err(io::Error::last_os_error());
+      }
+    }
+    Ok(())
+  }
+  /// Returns the active receive filters.
+  pub fn filters(&self) -> io::Result<Vec<Filter>> {
+    let mut raw = vec![libc::can_filter { can_id: 0, can_mask: 0 }; libc::CAN_RAW_FILTER_MAX as usize];
+    let mut len = mem::size_of_val(raw.as_slice()) as libc::socklen_t;
+    unsafe {
+      if libc::getsockopt(self.fd, libc::SOL_CAN_RAW, libc::CAN_RAW_FILTER, raw.as_mut_ptr() as *mut c_void, &mut len) < 0 {
+        return Err(io::Error::last_os_error());
+      }
+    }
+    raw.truncate(len as usize / mem::size_of::<libc::can_filter>());
+    Ok(raw.iter().map(|f| Filter { can_id: f.can_id, can_mask: f.can_mask }).collect())
+  }
+  /// If enabled, a frame has to pass all filters instead of any of them (`CAN_RAW_JOIN_FILTERS`).
+  pub fn set_join_filters(&self, join: bool) -> io::Result<()> {
+    self.set_flag(libc::SOL_CAN_RAW, libc::CAN_RAW_JOIN_FILTERS, join)
+  }
+  /// Enables the reception of error frames for the error classes in `mask`
+  /// (e.g. `ERR_MASK_ALL`, `ERR_MASK_NONE` or a combination of `ERR_BUSOFF`, `ERR_CRTL`, ...).
+  /// Error frames are received like normal frames and can be decoded with `Msg::error_frame`.
+  pub fn set_error_filter(&self, mask: u32) -> io::Result<()> {
+    unsafe {
+      if libc::setsockopt(self.fd, libc::SOL_CAN_RAW, libc::CAN_RAW_ERR_FILTER, &mask as *const u32 as *const c_void, mem::size_of::<u32>() as u32) < 0 {
+        return Err(io::Error::last_os_error());
+      }
+    }
+    Ok(())
+  }
+  /// Enables or disables the local loopback of sent frames (`CAN_RAW_LOOPBACK`, enabled by default).
+  /// If enabled, other sockets on the same host receive the frames sent by this socket.
+  pub fn set_loopback(&self, loopback: bool) -> io::Result<()> {
+    self.set_flag(libc::SOL_CAN_RAW, libc::CAN_RAW_LOOPBACK, loopback)
+  }
+  /// Enables or disables the reception of frames sent by this socket (`CAN_RAW_RECV_OWN_MSGS`, disabled by default).
+  /// Requires loopback to be enabled. The echoed frames are delivered once they were sent on the bus,
+  /// `Msg::is_tx_confirmation` tells them apart and `Msg::timestamp` returns the time of transmission.
+  pub fn set_recv_own_msgs(&self, recv_own_msgs: bool) -> io::Result<()> {
+    self.set_flag(libc::SOL_CAN_RAW, libc::CAN_RAW_RECV_OWN_MSGS, recv_own_msgs)
+  }
+  /// Enables or disables `SO_TIMESTAMPING` software and raw hardware RX timestamps,
+  /// which are returned by `Msg::timestamps`.
+  /// Hardware timestamps require support by the CAN driver.
+  pub fn set_timestamping(&self, software: bool, hardware: bool) -> io::Result<()> {
+    let mut flags: u32 = 0;
+    if software {
+      flags |= libc::SOF_TIMESTAMPING_RX_SOFTWARE | libc::SOF_TIMESTAMPING_SOFTWARE;
+    }
+    if hardware {
+      flags |= libc::SOF_TIMESTAMPING_RX_HARDWARE | libc::SOF_TIMESTAMPING_RAW_HARDWARE;
+    }
+    unsafe {
+      if libc::setsockopt(self.fd, libc::SOL_SOCKET, libc::SO_TIMESTAMPING, &flags as *const u32 as *const c_void, mem::size_of::<u32>() as u32) < 0 {
+        return Err(io::Error::last_os_error());
+      }
+    }
+    Ok(())
+  }
+  /// Enables or disables reporting the number of frames dropped by the kernel (`SO_RXQ_OVFL`),
+  /// which is returned by `Msg::drops`.
+  pub fn set_rxq_overflow(&self, rxq_overflow: bool) -> io::Result<()> {
+    self.set_flag(libc::SOL_SOCKET, libc::SO_RXQ_OVFL, rxq_overflow)
+  }
+  /// Enables or disables sending and receiving CAN XL frames (`CAN_RAW_XL_FRAMES`, Linux 6.2 or later).
+  /// CAN XL frames are received in addition to classic CAN and CAN FD frames.
+  pub fn set_xl_frames(&self, xl_frames: bool) -> io::Result<()> {
+    self.set_flag(libc::SOL_CAN_RAW, CAN_RAW_XL_FRAMES, xl_frames)
+  }
+  /// Configures the handling of the virtual CAN network id of CAN XL frames (`CAN_RAW_XL_VCID_OPTS`, Linux 6.9 or later).
+  pub fn set_xl_vcid_options(&self, opts: &XlVcidOptions) -> io::Result<()> {
+    let mut raw = [0u8; 4];
+    if let Some(vcid) = opts.tx_vcid {
+      raw[0] |= 0x01;
+      raw[1] = vcid;
+    }
+    if opts.tx_pass {
+      raw[0] |= 0x02;
+    }
+    if let Some((vcid, mask)) = opts.rx_filter {
+      raw[0] |= 0x04;
+      raw[2] = vcid;
+      raw[3] = mask;
+    }
+    unsafe {
+      if libc::setsockopt(self.fd, libc::SOL_CAN_RAW, CAN_RAW_XL_VCID_OPTS, raw.as_ptr() as *const c_void, raw.len() as u32) < 0 {
+        return Err(io::Error::last_os_error());
+      }
+    }
+    Ok(())
+  }
+  fn set_flag(&self, level: c_int, name: c_int, on: bool) -> io::Result<()> {
+    self.set_int(level, name, on as c_int)
+  }
+  /// Receives up to `batch.capacity()` messages with a single `recvmmsg` call.
+  /// Blocks like `Can::recv` until at least one frame is available, then takes all frames which are
+  /// available without blocking. Returns the number of received messages, which are accessible through `batch`.
+  pub fn recv_batch(&self, batch: &mut MsgBatch) -> io::Result<usize> {
+    unsafe {
+      batch.len = 0;
+      for (msg, hdr) in batch.msgs.iter_mut().zip(batch.hdrs.iter_mut()) {
+        msg.reset();
+        hdr.msg_hdr = msg.msg;
+        hdr.msg_len = 0;
+      }
+      let received = libc::recvmmsg(self.fd, batch.hdrs.as_mut_ptr(), batch.hdrs.len() as u32, libc::MSG_WAITFORONE, ptr::null_mut());
+      if received < 0 {
+        return Err(self.last_error());
+      }
+      for (msg, hdr) in batch.msgs.iter_mut().zip(batch.hdrs.iter()).take(received as usize) {
+        msg.msg.msg_namelen    = hdr.msg_hdr.msg_namelen;
+        msg.msg.msg_controllen = hdr.msg_hdr.msg_controllen;
+        msg.msg.msg_flags      = hdr.msg_hdr.msg_flags;
+        msg.nbytes             = hdr.msg_len as usize;
+      }
+      batch.len = received as usize;
+      Ok(batch.len)
+    }
+  }
+  /// Enables or disables the non-blocking mode (`O_NONBLOCK`).
+  pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+    unsafe {
+      let flags = libc::fcntl(self.fd, libc::F_GETFL);
+      if flags < 0 {
+        return Err(io::Error::last_os_error());
+      }
+      let flags = if nonblocking { flags | libc::O_NONBLOCK } else { flags & !libc::O_NONBLOCK };
+      if libc::fcntl(self.fd, libc::F_SETFL, flags) < 0 {
+        return Err(io::Error::last_os_error());
+      }
+    }
+    Ok(())
+  }
+  /// Returns true if the socket is in non-blocking mode.
+  pub fn is_nonblocking(&self) -> io::Result<bool> {
+    unsafe {
+      let flags = libc::fcntl(self.fd, libc::F_GETFL);
+      if flags < 0 {
+        return Err(io::Error::last_os_error());
+      }
+      Ok(flags & libc::O_NONBLOCK != 0)
+    }
+  }
+  /// Sets the timeout of `Can::recv` (`SO_RCVTIMEO`, µs granularity). None blocks forever.
+  pub fn set_recv_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+    self.set_timeout(libc::SO_RCVTIMEO, timeout)
+  }
+  /// Sets the timeout for sending frames (`SO_SNDTIMEO`, µs granularity). None blocks forever.
+  pub fn set_send_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+    self.set_timeout(libc::SO_SNDTIMEO, timeout)
+  }
+  fn set_timeout(&self, name: c_int, timeout: Option<Duration>) -> io::Result<()> {
+    // a zero timeval blocks forever, so positive timeouts are at least 1 µs
+    let us = match timeout {
+      Some(timeout) if timeout <= Duration::zero() => {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "timeout must be positive"));
+      }
+      Some(timeout) => timeout.num_microseconds().unwrap_or(i64::MAX).max(1),
+      None => 0,
+    };
+    let tv = libc::timeval { tv_sec: (us / 1_000_000) as libc::time_t, tv_usec: (us % 1_000_000) as libc::suseconds_t };
+    unsafe {
+      if libc::setsockopt(self.fd, libc::SOL_SOCKET, name, &tv as *const libc::timeval as *const c_void, mem::size_of::<libc::timeval>() as u32) < 0 {
+        return Err(io::Error::last_os_error());
+      }
+    }
+    Ok(())
+  }
+  /// Sets the size of the receive buffer in bytes (`SO_RCVBUF`). The kernel doubles the value.
+  pub fn set_recv_buffer_size(&self, size: usize) -> io::Result<()> {
+    self.set_int(libc::SOL_SOCKET, libc::SO_RCVBUF, size as c_int)
+  }
+  /// Get the size of the receive buffer in bytes.
+  pub fn recv_buffer_size(&self) -> io::Result<usize> {
+    self.get_int(libc::SOL_SOCKET, libc::SO_RCVBUF).map(|size| size as usize)
+  }
+  /// Sets the size of the send buffer in bytes (`SO_SNDBUF`). The kernel doubles the value.
+  pub fn set_send_buffer_size(&self, size: usize) -> io::Result<()> {
+    self.set_int(libc::SOL_SOCKET, libc::SO_SNDBUF, size as c_int)
+  }
+  /// Get the size of the send buffer in bytes.
+  pub fn send_buffer_size(&self) -> io::Result<usize> {
+    self.get_int(libc::SOL_SOCKET, libc::SO_SNDBUF).map(|size| size as usize)
+  }
+  fn set_int(&self, level: c_int, name: c_int, value: c_int) -> io::Result<()> {
+    unsafe {
+      if libc::setsockopt(self.fd, level, name, &value as *const c_int as *const c_void, mem::size_of::<c_int>() as u32) < 0 {
+        return Err(io::Error::last_os_error());
+      }
+    }
+    Ok(())
+  }
+  fn get_int(&self, level: c_int, name: c_int) -> io::Result<c_int> {
+    let mut value: c_int = 0;
+    let mut len = mem::size_of::<c_int>() as libc::socklen_t;
+    unsafe {
+      if libc::getsockopt(self.fd, level, name, &mut value as *mut c_int as *mut c_void, &mut len) < 0 {
+        return Err(io::Error::last_os_error());
+      }
+    }
+    Ok(value)
+  }
+  /// Returns the last OS error. `EAGAIN` is reported as `io::ErrorKind::WouldBlock` in non-blocking
+  /// mode and as `io::ErrorKind::TimedOut` if a receive or send timeout expired.
+  fn last_error(&self) -> io::Error {
+    let err = io::Error::last_os_error();
+    if err.kind() == io::ErrorKind::WouldBlock && !self.is_nonblocking().unwrap_or(true) {
+      return io::Error::new(io::ErrorKind::TimedOut, "CAN socket timed out");
+    }
+    err
+  }
   /// Receives a CAN message.
-  /// Blocks until frame is received or the iface is down.
+  /// Blocks until frame is received or the iface is down, unless the socket is non-blocking
+  /// (error of kind `io::ErrorKind::WouldBlock` if no frame is available) or a receive timeout
+  /// is set (error of kind `io::ErrorKind::TimedOut` if it expired).
   pub fn recv(&self, msg: &mut Msg) -> Result<(), io::Error> {
     unsafe {
       msg.reset();
-      let nbytes = libc::recvmsg(self.fd, mem::transmute(&msg.msg), 0);
+      let nbytes = libc::recvmsg(self.fd, &mut msg.msg, 0);
       if nbytes < 0 {
-        return Err(io::Error::last_os_error());
+        return Err(self.last_error());
       }
+      msg.nbytes = nbytes as usize;
     }
     Ok(())
   }
 }
+impl std::os::unix::io::AsRawFd for Can {
+  fn as_raw_fd(&self) -> std::os::unix::io::RawFd {
+    self.fd
+  }
+}
+impl std::os::unix::io::AsFd for Can {
+  fn as_fd(&self) -> std::os::unix::io::BorrowedFd<'_> {
+    unsafe { std::os::unix::io::BorrowedFd::borrow_raw(self.fd) }
+  }
+}
+impl std::os::unix::io::FromRawFd for Can {
+  /// Takes ownership of the fd, which must be an open CAN socket.
+  unsafe fn from_raw_fd(fd: std::os::unix::io::RawFd) -> Can {
+    Can { fd }
+  }
+}
+impl std::os::unix::io::IntoRawFd for Can {
+  /// Releases ownership of the fd, it is not closed anymore.
+  fn into_raw_fd(self) -> std::os::unix::io::RawFd {
+    let fd = self.fd;
+    mem::forget(self);
+    fd
+  }
+}
+#[cfg(feature = "mio")]
+impl mio::event::Source for Can {
+  fn register(&mut self, registry: &mio::Registry, token: mio::Token, interests: mio::Interest) -> io::Result<()> {
+    mio::unix::SourceFd(&self.fd).register(registry, token, interests)
+  }
+  fn reregister(&mut self, registry: &mio::Registry, token: mio::Token, interests: mio::Interest) -> io::Result<()> {
+    mio::unix::SourceFd(&self.fd).reregister(registry, token, interests)
+  }
+  fn deregister(&mut self, registry: &mio::Registry) -> io::Result<()> {
+    mio::unix::SourceFd(&self.fd).deregister(registry)
+  }
+}
 impl Drop for Can {
   fn drop(&mut self) {
     unsafe {
@@ -141,124 +595,504 @@ impl Drop for Can {
   }
 }
 struct CanData<T> {
-  can: Can, 
+  can: Socket,
   user_data: T,
+  drops: u32,
 }
 /// CAN message type.
 pub struct Msg {
   msg: libc::msghdr,
   addr: libc::sockaddr_can,
-  iov: libc::iovec,
-  frame: libc::canfd_frame,
+  iov: [libc::iovec; 2],
+  frame: RawFrame,
+  bcm: bcm::BcmMsgHead,
+  pdu: Vec<u8>,
+  proto: c_int,
+  nbytes: usize,
   ctrlmsg: [u8; unsafe { libc::CMSG_SPACE(mem::size_of::<libc::timeval>() as u32) + 
                          libc::CMSG_SPACE(3 * mem::size_of::<libc::timespec>() as u32) +
-                         libc::CMSG_SPACE(mem::size_of::<u32>() as u32) } as usize],
+                         libc::CMSG_SPACE(mem::size_of::<u32>() as u32) +
+                         2 * libc::CMSG_SPACE(mem::size_of::<u8>() as u32) +
+                         libc::CMSG_SPACE(mem::size_of::<u64>() as u32) } as usize],
 }
+// The pointers of msg and iov only point into the Msg itself or its pdu buffer. They are re-derived
+// before each syscall (`Msg::reset`) and before reading the control messages (`Msg::hdr`), so a
+// moved Msg never uses stale pointers, and they are only written through &mut. Hence the message
+// can be sent to and shared with other threads.
+unsafe impl Send for Msg {}
+unsafe impl Sync for Msg {}
 impl Msg {
   /// Return initialized empty message object.
-  /// The return type is Box, to avoid dangling pointers
-  /// when the object is moved.
+  /// The return type is Box, as the message is too large to be moved around cheaply.
   pub fn new() -> Box<Msg> {
     unsafe {
-      let ucm             = mem::MaybeUninit::<Msg>::uninit();
-      let mut msg         = Box::<Msg>::new(mem::transmute(ucm));
-      msg.msg.msg_iovlen  = 1;
-      msg.iov.iov_base    = mem::transmute(&(*msg).frame);
-      msg.msg.msg_name    = mem::transmute(&(*msg).addr);
-      msg.msg.msg_iov     = mem::transmute(&(*msg).iov);
-      msg.msg.msg_control = mem::transmute(&(*msg).ctrlmsg[0]);
+      let mut msg         = Box::new(Msg {
+        msg:     mem::zeroed(),
+        addr:    mem::zeroed(),
+        iov:     mem::zeroed(),
+        frame:   mem::zeroed(),
+        bcm:     mem::zeroed(),
+        pdu:     Vec::new(),
+        proto:   0,
+        nbytes:  0,
+        ctrlmsg: mem::zeroed(),
+      });
       msg.reset();
       msg
     }
   }
   fn reset(&mut self) {
+    self.msg.msg_name       = &mut self.addr as *mut libc::sockaddr_can as *mut c_void;
+    self.msg.msg_iov        = self.iov.as_mut_ptr();
+    self.msg.msg_control    = self.ctrlmsg.as_mut_ptr() as *mut c_void;
     self.msg.msg_iovlen     = 1;
-    self.iov.iov_len        = mem::size_of::<libc::canfd_frame>();
+    self.iov[0].iov_base    = &mut self.frame as *mut RawFrame as *mut c_void;
+    self.iov[0].iov_len     = mem::size_of::<RawFrame>();
+    self.proto              = libc::CAN_RAW;
     self.msg.msg_namelen    = mem::size_of::<libc::sockaddr_can>() as u32;
     self.msg.msg_controllen = mem::size_of_val(&self.ctrlmsg);
     self.msg.msg_flags      = 0;
+    self.nbytes             = 0;
   }
+  // Header of the last received message, pointing into this Msg even if it was moved since.
+  fn hdr(&self) -> libc::msghdr {
+    let mut hdr = self.msg;
+    hdr.msg_name    = &self.addr as *const libc::sockaddr_can as *mut c_void;
+    hdr.msg_iov     = self.iov.as_ptr() as *mut libc::iovec;
+    hdr.msg_control = self.ctrlmsg.as_ptr() as *mut c_void;
+    hdr
+  }
+
   /// Get CAN ID.
+  /// For CAN XL frames (see `Msg::is_xl`) use `Msg::frame` instead of `can_id`, `len`, `flags` and indexing.
   pub fn can_id(&self) -> u32 {
-    self.frame.can_id
+    unsafe { self.frame.fd.can_id }
   }
   /// Get DLC.
   pub fn len(&self) -> u8 {
-    self.frame.len
+    unsafe { self.frame.fd.len }
+  }
+  /// Returns true if the frame carries no data.
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
   }
   /// Get CAN FD flags.
   pub fn flags(&self) -> u8 {
-    self.frame.flags
+    unsafe { self.frame.fd.flags }
+  }
+  /// Returns true if a CAN FD frame was received.
+  pub fn is_fd(&self) -> bool {
+    self.nbytes == libc::CANFD_MTU && !self.is_xl()
+  }
+  /// Returns true if a CAN XL frame was received.
+  pub fn is_xl(&self) -> bool {
+    self.frame.is_xl(self.nbytes)
   }
-  /// Get the frame timestamp.
+  /// Converts the received frame into an owned frame.
+  /// Returns None for error frames or if nothing has been received yet.
+  /// For messages received from broadcast manager sockets, this is the frame attached to the notification.
+  pub fn frame(&self) -> Option<Frame> {
+    if self.proto == libc::CAN_ISOTP || self.proto == libc::CAN_J1939 {
+      return None;
+    }
+    if self.proto == libc::CAN_BCM {
+      let fd = self.bcm.flags & bcm::CAN_FD_FRAME != 0;
+      let len = (self.len() as usize).min(if fd { libc::CANFD_MAX_DLEN } else { libc::CAN_MAX_DLEN });
+      if self.nbytes < bcm::HEAD_SIZE + if fd { libc::CANFD_MTU } else { libc::CAN_MTU } {
+        return None;
+      }
+      return Frame::from_raw(self.can_id(), self.flags(), unsafe { &self.frame.fd.data[..len] }, fd);
+    }
+    if self.is_xl() {
+      return Some(Frame::Xl(CanXlFrame::from_raw(unsafe { &self.frame.xl })));
+    }
+    if self.nbytes != libc::CAN_MTU && self.nbytes != libc::CANFD_MTU {
+      return None;
+    }
+    let fd = unsafe { &self.frame.fd };
+    let len = (fd.len as usize).min(if self.is_fd() { libc::CANFD_MAX_DLEN } else { libc::CAN_MAX_DLEN });
+    Frame::from_raw(fd.can_id, fd.flags, &fd.data[..len], self.is_fd())
+  }
+  /// Get the index of the interface the frame was received from.
+  pub fn ifindex(&self) -> i32 {
+    self.addr.can_ifindex
+  }
+  /// Get the name of the interface the frame was received from.
+  pub fn ifname(&self) -> io::Result<String> {
+    let mut buf = [0 as c_char; libc::IF_NAMESIZE];
+    unsafe {
+      if libc::if_indextoname(self.addr.can_ifindex as u32, buf.as_mut_ptr()).is_null() {
+        return Err(io::Error::last_os_error());
+      }
+      Ok(std::ffi::CStr::from_ptr(buf.as_ptr()).to_string_lossy().into_owned())
+    }
+  }
+  /// Returns true if the frame was sent from the local host (`MSG_DONTROUTE`),
+  /// either by this or by another socket.
+  pub fn is_local(&self) -> bool {
+    self.msg.msg_flags & libc::MSG_DONTROUTE != 0
+  }
+  /// Returns true if the frame was sent by the socket which received it (`MSG_CONFIRM`),
+  /// i.e. it confirms that the frame was transmitted.
+  pub fn is_tx_confirmation(&self) -> bool {
+    self.msg.msg_flags & libc::MSG_CONFIRM != 0
+  }
+  /// Decodes the notification received from a broadcast manager socket (`Bcm::recv`).
+  /// Returns None for messages received from other sockets.
+  pub fn bcm_event(&self) -> Option<BcmEvent> {
+    if self.proto != libc::CAN_BCM || self.nbytes < bcm::HEAD_SIZE {
+      return None;
+    }
+    bcm::decode_event(&self.bcm, self.frame())
+  }
+  /// Get the PDU received from an ISO-TP (`IsoTp::recv`) or J1939 socket (`J1939::recv`).
+  /// Returns None for messages received from other sockets.
+  pub fn pdu(&self) -> Option<&[u8]> {
+    if self.proto != libc::CAN_ISOTP && self.proto != libc::CAN_J1939 {
+      return None;
+    }
+    Some(&self.pdu[..self.nbytes])
+  }
+  /// Get the PGN, priority and addresses of a message received from a J1939 socket (`J1939::recv`).
+  /// Returns None for messages received from other sockets.
+  pub fn j1939(&self) -> Option<J1939Header> {
+    if self.proto != libc::CAN_J1939 {
+      return None;
+    }
+    Some(j1939::header(&self.hdr(), &self.addr))
+  }
+  /// Decodes an address claimed message received from a J1939 socket. Returns None if it isn't one.
+  pub fn address_claim(&self) -> Option<AddressClaim> {
+    j1939::decode_address_claim(&self.j1939()?, self.pdu()?)
+  }
+  /// Decodes the received frame as error frame. Returns None if it isn't one.
+  pub fn error_frame(&self) -> Option<ErrorFrame> {
+    if self.proto != libc::CAN_RAW || self.nbytes != libc::CAN_MTU || self.is_xl() {
+      return None;
+    }
+    let fd = unsafe { &self.frame.fd };
+    ErrorFrame::decode(fd.can_id, &fd.data[..libc::CAN_MAX_DLEN])
+  }
+  /// Get the software timestamp of the frame (time since the UNIX epoch). The `SO_TIMESTAMP` stamp,
+  /// which is always enabled, has µs resolution; enable `Can::set_timestamping` and use
+  /// `Msg::timestamps` for ns resolution.
   pub fn timestamp(&self) -> io::Result<Duration> {
+    self.timestamps().software.ok_or_else(|| io::Error::new(io::ErrorKind::Unsupported, "timestamps aren't supported"))
+  }
+  /// Get the number of frames the kernel dropped on this socket so far, because its receive queue was full.
+  /// The counter is cumulative and wraps around. Returns None if `Can::set_rxq_overflow` isn't enabled.
+  pub fn drops(&self) -> Option<u32> {
+    let hdr = self.hdr();
     unsafe {
-      let mut cmsg = libc::CMSG_FIRSTHDR(&self.msg);
-      loop {
-        if cmsg.is_null() || (*cmsg).cmsg_level != libc::SOL_SOCKET {
-          break
+      let mut cmsg = libc::CMSG_FIRSTHDR(&hdr);
+      while !cmsg.is_null() {
+        if (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SO_RXQ_OVFL {
+          return Some(ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const u32));
         }
-        match (*cmsg).cmsg_type {
-          libc::SO_TIMESTAMP => {
-            let tv = libc::CMSG_DATA(cmsg) as *const libc::timeval;            
-            return Ok(Duration::milliseconds(((*tv).tv_sec * 1000000000 + (*tv).tv_usec * 1000) as i64));
-          }
-          libc::SO_TIMESTAMPING => {
-            let ts = libc::CMSG_DATA(cmsg) as *const libc::timespec; 
-            return Ok(Duration::milliseconds(((*ts).tv_sec * 1000000000 + (*ts).tv_nsec) as i64));
-          }
-          _ => {
-          }
-        };
-        cmsg = libc::CMSG_NXTHDR(&self.msg, cmsg);
+        cmsg = libc::CMSG_NXTHDR(&hdr, cmsg);
       }
     }
-    Err(io::Error::new(io::ErrorKind::Unsupported, "timestamps aren't supported"))
+    None
+  }
+  /// Get all timestamps available for the frame.
+  /// Hardware timestamps are only reported after enabling them with `Can::set_timestamping`.
+  pub fn timestamps(&self) -> Timestamps {
+    let mut stamps = Timestamps { software: None, hardware: None };
+    let hdr = self.hdr();
+    unsafe {
+      let mut cmsg = libc::CMSG_FIRSTHDR(&hdr);
+      while !cmsg.is_null() {
+        if (*cmsg).cmsg_level == libc::SOL_SOCKET {
+          match (*cmsg).cmsg_type {
+            libc::SO_TIMESTAMP => {
+              let tv = ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const libc::timeval);
+              stamps.software = Some(Duration::seconds(tv.tv_sec as i64) + Duration::microseconds(tv.tv_usec as i64));
+            }
+            libc::SO_TIMESTAMPING => {
+              // [0]: software stamp, [1]: deprecated, [2]: raw hardware stamp
+              let ts = ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const [libc::timespec; 3]);
+              if let Some(software) = timespec_to_duration(&ts[0]) {
+                stamps.software = Some(software);
+              }
+              stamps.hardware = timespec_to_duration(&ts[2]);
+            }
+            _ => {
+            }
+          };
+        }
+        cmsg = libc::CMSG_NXTHDR(&hdr, cmsg);
+      }
+    }
+    stamps
+  }
+}
+// time_t and c_long are narrower than i64 on 32 bit targets
+#[allow(clippy::unnecessary_cast)]
+fn timespec_to_duration(ts: &libc::timespec) -> Option<Duration> {
+  if ts.tv_sec == 0 && ts.tv_nsec == 0 {
+    return None;
+  }
+  Some(Duration::seconds(ts.tv_sec as i64) + Duration::nanoseconds(ts.tv_nsec as i64))
+}
+/// Timestamps of a received frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Timestamps {
+  /// time the kernel received the frame, relative to the UNIX epoch
+  pub software: Option<Duration>,
+  /// time the controller received the frame, relative to the (driver specific) epoch of the hardware clock
+  pub hardware: Option<Duration>,
+}
+/// Received frame with its timestamps.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TimestampedFrame {
+  /// the frame
+  pub frame: Frame,
+  /// timestamps of the frame
+  pub timestamps: Timestamps,
+}
+impl TimestampedFrame {
+  /// Converts the received message, returns None if `Msg::frame` does.
+  pub fn from_msg(msg: &Msg) -> Option<TimestampedFrame> {
+    Some(TimestampedFrame { frame: msg.frame()?, timestamps: msg.timestamps() })
   }
 }
 impl Index<usize> for Msg {
   type Output = u8;
   fn index(&self, index: usize) -> &u8 {
-    &self.frame.data[index]
+    unsafe { &self.frame.fd.data[index] }
+  }
+}
+/// Handling of the virtual CAN network id (VCID) of CAN XL frames, see `Can::set_xl_vcid_options`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct XlVcidOptions {
+  /// VCID set by the kernel on all sent frames
+  pub tx_vcid: Option<u8>,
+  /// pass the VCID of the sent frames (`CanXlFrame::with_vcid`) to the bus
+  pub tx_pass: bool,
+  /// receive only frames with `frame_vcid & mask == vcid & mask` (vcid, mask).
+  /// Without filter, frames tagged with a VCID are not received.
+  pub rx_filter: Option<(u8, u8)>,
+}
+/// Buffer for receiving multiple messages at once with `Can::recv_batch`.
+pub struct MsgBatch {
+  // boxed, because each Msg points into itself
+  #[allow(clippy::vec_box)]
+  msgs: Vec<Box<Msg>>,
+  hdrs: Vec<libc::mmsghdr>,
+  len: usize,
+}
+// hdrs only point into the boxed messages owned by the batch
+unsafe impl Send for MsgBatch {}
+unsafe impl Sync for MsgBatch {}
+impl MsgBatch {
+  /// Creates a batch which receives up to `capacity` messages per call.
+  pub fn new(capacity: usize) -> MsgBatch {
+    MsgBatch {
+      msgs: (0..capacity.max(1)).map(|_| Msg::new()).collect(),
+      hdrs: (0..capacity.max(1)).map(|_| unsafe { mem::zeroed() }).collect(),
+      len:  0,
+    }
+  }
+  /// Get the maximum number of messages received per call.
+  pub fn capacity(&self) -> usize {
+    self.msgs.len()
+  }
+  /// Get the number of messages received by the last call to `Can::recv_batch`.
+  pub fn len(&self) -> usize {
+    self.len
+  }
+  /// Returns true if no messages were received.
+  pub fn is_empty(&self) -> bool {
+    self.len == 0
+  }
+  /// Iterates over the received messages.
+  pub fn iter(&self) -> impl Iterator<Item = &Msg> {
+    self.msgs[..self.len].iter().map(|msg| &**msg)
+  }
+}
+impl Index<usize> for MsgBatch {
+  type Output = Msg;
+  fn index(&self, index: usize) -> &Msg {
+    &self.msgs[..self.len][index]
+  }
+}
+/// Socket which can be added to a `CanGroup`.
+pub enum Socket {
+  /// raw CAN socket
+  Can(Can),
+  /// broadcast manager socket
+  Bcm(Bcm),
+  /// ISO-TP socket
+  IsoTp(IsoTp),
+  /// J1939 socket
+  J1939(J1939),
+}
+impl Socket {
+  fn fd(&self) -> c_int {
+    match self {
+      Socket::Can(can) => can.fd,
+      Socket::Bcm(bcm) => bcm.fd.raw(),
+      Socket::IsoTp(isotp) => isotp.fd.raw(),
+      Socket::J1939(j1939) => j1939.fd.raw(),
+    }
+  }
+  fn recv(&self, msg: &mut Msg) -> io::Result<()> {
+    match self {
+      Socket::Can(can) => can.recv(msg),
+      Socket::Bcm(bcm) => bcm.recv(msg),
+      Socket::IsoTp(isotp) => isotp.recv(msg),
+      Socket::J1939(j1939) => j1939.recv(msg),
+    }
+  }
+}
+impl From<Can> for Socket {
+  fn from(can: Can) -> Socket {
+    Socket::Can(can)
+  }
+}
+impl From<Bcm> for Socket {
+  fn from(bcm: Bcm) -> Socket {
+    Socket::Bcm(bcm)
+  }
+}
+impl From<IsoTp> for Socket {
+  fn from(isotp: IsoTp) -> Socket {
+    Socket::IsoTp(isotp)
+  }
+}
+impl From<J1939> for Socket {
+  fn from(j1939: J1939) -> Socket {
+    Socket::J1939(j1939)
+  }
+}
+/// Handle of a member of a `CanGroup`, returned by `CanGroup::add`. Handles stay valid until
+/// the member is removed and are never reused by the same group.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct CanHandle(u64);
+/// Error of `CanGroup::next`.
+#[derive(Debug)]
+pub enum CanGroupError {
+  /// waiting for the members failed
+  Wait(io::Error),
+  /// receiving from the members failed, e.g. because their CAN adapter was unplugged (`ENODEV`).
+  /// The frames of the other members were delivered nevertheless.
+  Members(Vec<(CanHandle, io::Error)>),
+}
+impl std::fmt::Display for CanGroupError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      CanGroupError::Wait(err) => write!(f, "waiting for the CanGroup members failed: {}", err),
+      CanGroupError::Members(errors) => {
+        write!(f, "receiving from {} CanGroup member(s) failed", errors.len())?;
+        for (handle, err) in errors {
+          write!(f, ", {:?}: {}", handle, err)?;
+        }
+        Ok(())
+      }
+    }
+  }
+}
+impl std::error::Error for CanGroupError {}
+impl From<io::Error> for CanGroupError {
+  fn from(err: io::Error) -> CanGroupError {
+    CanGroupError::Wait(err)
+  }
+}
+impl From<CanGroupError> for io::Error {
+  fn from(err: CanGroupError) -> io::Error {
+    match err {
+      CanGroupError::Wait(err) => err,
+      CanGroupError::Members(ref errors) => {
+        let kind = errors.first().map_or(io::ErrorKind::Other, |(_, err)| err.kind());
+        io::Error::new(kind, err)
+      }
+    }
   }
 }
 /// Type for receiving data from multiple CAN devices. This type also supports timeouts.
 pub struct CanGroup<T> {
   fd_epoll: c_int,
-  cans: Vec<CanData<T>>,
+  cans: BTreeMap<CanHandle, CanData<T>>,
+  next_handle: u64,
   events: Vec<libc::epoll_event>,
   msg: Box<Msg>,
+  dropped: u32,
 }
 impl<T> CanGroup<T> {
   /// Creates an empty CanGroup instance.
   pub fn new() -> CanGroup<T> {
     unsafe {
       CanGroup::<T> {
-        fd_epoll: libc::epoll_create(1),
-        cans:     Vec::new(),
-        events:   Vec::new(),
-        msg:      Msg::new(),
+        fd_epoll:    libc::epoll_create(1),
+        cans:        BTreeMap::new(),
+        next_handle: 0,
+        events:      vec![mem::zeroed()],
+        msg:         Msg::new(),
+        dropped:     0,
       }
     }
   }
-  /// Adds a can device to the group
-  pub fn add(&mut self, can: Can, user_data: T) -> io::Result<()> {
-    unsafe {
-      for i in 0..self.events.len() {
-        if libc::epoll_ctl(self.fd_epoll, libc::EPOLL_CTL_DEL, self.cans[i].can.fd, 0 as *mut libc::epoll_event) != 0 {
-          return Err(io::Error::last_os_error());
-        }
-      }
-      self.cans.push(CanData { can: can, user_data: user_data });
-      self.events.push(mem::transmute(mem::MaybeUninit::<libc::epoll_event>::uninit()));
-      for i in 0..self.events.len() {
-        self.events[i].events = libc::EPOLLIN as u32;
-        self.events[i].u64 = self.cans.last().unwrap() as *const _ as u64;
-        let eptr = self.events.as_mut_ptr();
-        if libc::epoll_ctl(self.fd_epoll, libc::EPOLL_CTL_ADD, self.cans[i].can.fd, eptr.add(i)) != 0 {
-          return Err(io::Error::last_os_error());
-        }
-      }
+  /// Adds a can device (`Can`) or another CAN socket (`Bcm`, `IsoTp`, `J1939`) to the group and
+  /// returns the handle to remove or replace it later on.
+  pub fn add<S: Into<Socket>>(&mut self, can: S, user_data: T) -> io::Result<CanHandle> {
+    let can = can.into();
+    let handle = CanHandle(self.next_handle);
+    self.epoll_ctl(libc::EPOLL_CTL_ADD, can.fd(), handle)?;
+    self.next_handle += 1;
+    self.cans.insert(handle, CanData { can, user_data, drops: 0 });
+    self.events.resize(self.cans.len(), unsafe { mem::zeroed() });
+    Ok(handle)
+  }
+  /// Removes the member from the group and returns its socket and user data, e.g. after
+  /// the CAN adapter was unplugged. Returns `None` if the handle is not part of the group.
+  pub fn remove(&mut self, handle: CanHandle) -> Option<(Socket, T)> {
+    let can_data = self.cans.remove(&handle)?;
+    // the kernel removes closed fds on its own, so the member is gone even if this fails
+    let _ = self.epoll_ctl(libc::EPOLL_CTL_DEL, can_data.can.fd(), handle);
+    Some((can_data.can, can_data.user_data))
+  }
+  /// Replaces the socket of the member, e.g. after the CAN adapter was plugged in again, and
+  /// returns the old one. The handle and the user data are kept.
+  pub fn replace<S: Into<Socket>>(&mut self, handle: CanHandle, can: S) -> io::Result<Socket> {
+    let can = can.into();
+    let old_fd = match self.cans.get(&handle) {
+      Some(can_data) => can_data.can.fd(),
+      None => return Err(io::Error::new(io::ErrorKind::NotFound, "no such CanGroup member")),
+    };
+    self.epoll_ctl(libc::EPOLL_CTL_ADD, can.fd(), handle)?;
+    let _ = self.epoll_ctl(libc::EPOLL_CTL_DEL, old_fd, handle);
+    let can_data = self.cans.get_mut(&handle).unwrap();
+    can_data.drops = 0;
+    Ok(mem::replace(&mut can_data.can, can))
+  }
+  /// Returns the socket and the user data of the member.
+  pub fn get(&self, handle: CanHandle) -> Option<(&Socket, &T)> {
+    self.cans.get(&handle).map(|can_data| (&can_data.can, &can_data.user_data))
+  }
+  /// Returns the socket and the mutable user data of the member.
+  pub fn get_mut(&mut self, handle: CanHandle) -> Option<(&Socket, &mut T)> {
+    self.cans.get_mut(&handle).map(|can_data| (&can_data.can, &mut can_data.user_data))
+  }
+  /// Iterates over the members in the order they were added.
+  pub fn iter(&self) -> impl Iterator<Item = (CanHandle, &Socket, &T)> {
+    self.cans.iter().map(|(handle, can_data)| (*handle, &can_data.can, &can_data.user_data))
+  }
+  /// Iterates over the members in the order they were added, with mutable user data.
+  pub fn iter_mut(&mut self) -> impl Iterator<Item = (CanHandle, &Socket, &mut T)> {
+    self.cans.iter_mut().map(|(handle, can_data)| (*handle, &can_data.can, &mut can_data.user_data))
+  }
+  /// Returns the number of members.
+  pub fn len(&self) -> usize {
+    self.cans.len()
+  }
+  /// Returns true if the group has no members.
+  pub fn is_empty(&self) -> bool {
+    self.cans.is_empty()
+  }
+  fn epoll_ctl(&self, op: c_int, fd: c_int, handle: CanHandle) -> io::Result<()> {
+    let mut event = libc::epoll_event { events: libc::EPOLLIN as u32, u64: handle.0 };
+    if unsafe { libc::epoll_ctl(self.fd_epoll, op, fd, &mut event) } != 0 {
+      return Err(io::Error::last_os_error());
     }
     Ok(())
   }
@@ -267,24 +1101,74 @@ impl<T> CanGroup<T> {
   /// at least one CAN devices has new data available, until timeout is reached or until an error happend.
   /// The timeout uses ms granularity. Duration::from_milliseconds(-1) can be passed to this function
   /// to disable the timeout functionallity. The function either returns true, if no timeout happened,
-  /// false if a timeout happend or io::Error if an error happend.
-  pub fn next(&mut self, timeout: Duration, on_recv: fn(&Box<Msg>, &T)) -> Result<bool, io::Error> {
-    unsafe {
-      let mut no_timeout = false;
-      let num_events = libc::epoll_wait(self.fd_epoll, self.events.as_mut_ptr(), self.events.len() as i32, timeout.num_milliseconds() as i32);
-      if num_events == -1 {
-        return Err(io::Error::last_os_error());
+  /// false if a timeout happend or CanGroupError if an error happend. The callback gets mutable access to
+  /// the user data of the CAN device the frame was received from. If receiving from members fails, the
+  /// frames of the other members are still delivered and `CanGroupError::Members` reports the handles
+  /// of the failed members, e.g. to remove or replace them.
+  pub fn next<F: FnMut(&Msg, &mut T)>(&mut self, timeout: Duration, mut on_recv: F) -> Result<bool, CanGroupError> {
+    self.dropped = 0;
+    let num_events = unsafe {
+      libc::epoll_wait(self.fd_epoll, self.events.as_mut_ptr(), self.events.len() as i32, timeout.num_milliseconds() as i32)
+    };
+    if num_events == -1 {
+      return Err(CanGroupError::Wait(io::Error::last_os_error()));
+    }
+    let mut failed = Vec::new();
+    for event in &self.events[..num_events as usize] {
+      let handle = CanHandle(event.u64);
+      let can_data = match self.cans.get_mut(&handle) {
+        Some(can_data) => can_data,
+        None => continue,
+      };
+      if let Err(err) = can_data.can.recv(&mut self.msg) {
+        failed.push((handle, err));
+        continue;
       }
-      if num_events > 0 {
-        no_timeout = true;
-        for i in 0..num_events {
-          let can_data = self.events[i as usize].u64 as *mut CanData<T>;
-          (*can_data).can.recv(&mut self.msg)?;
-          on_recv(&self.msg, &(*can_data).user_data);
-        }
+      if let Some(drops) = self.msg.drops() {
+        self.dropped = self.dropped.wrapping_add(drops.wrapping_sub(can_data.drops));
+        can_data.drops = drops;
       }
-      Ok(no_timeout)
+      on_recv(&self.msg, &mut can_data.user_data);
+    }
+    if !failed.is_empty() {
+      return Err(CanGroupError::Members(failed));
     }
+    Ok(num_events > 0)
+  }
+  /// Returns the number of frames the kernel dropped, because the receive queues of the group
+  /// members were full, before the frames delivered by the last call to `CanGroup::next`.
+  /// Requires `Can::set_rxq_overflow` to be enabled on the members.
+  pub fn dropped(&self) -> u32 {
+    self.dropped
+  }
+}
+impl<T> Default for CanGroup<T> {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+impl<T> std::os::unix::io::AsRawFd for CanGroup<T> {
+  /// The epoll fd, readable when a member received a frame.
+  fn as_raw_fd(&self) -> std::os::unix::io::RawFd {
+    self.fd_epoll
+  }
+}
+impl<T> std::os::unix::io::AsFd for CanGroup<T> {
+  fn as_fd(&self) -> std::os::unix::io::BorrowedFd<'_> {
+    unsafe { std::os::unix::io::BorrowedFd::borrow_raw(self.fd_epoll) }
+  }
+}
+#[cfg(feature = "mio")]
+impl<T> mio::event::Source for CanGroup<T> {
+  /// Registers the epoll fd, call `CanGroup::next` with a zero timeout once it is readable.
+  fn register(&mut self, registry: &mio::Registry, token: mio::Token, interests: mio::Interest) -> io::Result<()> {
+    mio::unix::SourceFd(&self.fd_epoll).register(registry, token, interests)
+  }
+  fn reregister(&mut self, registry: &mio::Registry, token: mio::Token, interests: mio::Interest) -> io::Result<()> {
+    mio::unix::SourceFd(&self.fd_epoll).reregister(registry, token, interests)
+  }
+  fn deregister(&mut self, registry: &mio::Registry) -> io::Result<()> {
+    mio::unix::SourceFd(&self.fd_epoll).deregister(registry)
   }
 }
 impl<T> Drop for CanGroup<T> {
@@ -298,28 +1182,281 @@ impl<T> Drop for CanGroup<T> {
   }
 }
 
+/// Returns two connected datagram sockets, which stand in for a CAN bus in tests.
+#[cfg(test)]
+pub(crate) fn socket_pair() -> (Can, Can) {
+  use std::os::unix::io::IntoRawFd;
+  let (a, b) = std::os::unix::net::UnixDatagram::pair().unwrap();
+  (Can { fd: a.into_raw_fd() }, Can { fd: b.into_raw_fd() })
+}
+
 #[cfg(test)]
-fn on_recv(msg: &Box<Msg>, _user_data: &u64) {
+fn on_recv(msg: &Msg, _user_data: &mut u64) {
   println!("timestamp: {:?}", msg.timestamp());
   print!("received CAN frame (id: {}): ", msg.can_id());
   for i in 0..msg.len() {
     print!("{} ", msg[i as usize]);
   }
-  println!("");
+  println!();
 }
 
 #[cfg(test)]
 mod tests {
   use super::*;
   #[test]
+  #[ignore = "requires the vcan module and CAP_NET_ADMIN"]
   fn it_works() {
-    let can = Can::open("vcan0").unwrap();
+    // deletes the interface even if the test panics
+    struct Vcan(Option<CanInterface>);
+    impl Drop for Vcan {
+      fn drop(&mut self) {
+        if let Some(iface) = self.0.take() {
+          let _ = iface.delete();
+        }
+      }
+    }
+    let ifname = format!("sc2test{}", std::process::id() % 100_000);
+    let vcan = Vcan(Some(CanInterface::create_vcan(&ifname).unwrap()));
+    vcan.0.as_ref().unwrap().set_up().unwrap();
+    let can = Can::open(&ifname).unwrap();
+    let sender = Can::open(&ifname).unwrap();
     let mut cg = CanGroup::<u64>::new();
     cg.add(can, 0).unwrap();
-    match cg.next(Duration::milliseconds(-1), on_recv) {
+    sender.send(0x123, &[1, 2, 3]).unwrap();
+    match cg.next(Duration::seconds(1), on_recv) {
       Ok(no_timeout) => if !no_timeout { panic!("timeout"); },
       Err(_) => panic!("error"),
     }
   }
+  #[test]
+  fn group_closure() {
+    let (tx, rx) = socket_pair();
+    let mut cg = CanGroup::new();
+    cg.add(rx, 0u32).unwrap();
+    let mut ids = Vec::new();
+    for id in [0x10, 0x20] {
+      tx.send(id, &[]).unwrap();
+      assert!(cg.next(Duration::seconds(1), |msg, count| {
+        *count += 1;
+        ids.push((msg.can_id(), *count));
+      }).unwrap());
+    }
+    assert_eq!(ids, [(0x10, 1), (0x20, 2)]);
+  }
+  #[test]
+  fn group_membership() {
+    let (tx_a, rx_a) = socket_pair();
+    let (tx_b, rx_b) = socket_pair();
+    let mut cg = CanGroup::new();
+    let a = cg.add(rx_a, 'a').unwrap();
+    let b = cg.add(rx_b, 'b').unwrap();
+    assert_eq!(cg.iter().map(|(handle, _, name)| (handle, *name)).collect::<Vec<_>>(), [(a, 'a'), (b, 'b')]);
+    // every member delivers its frames with its own user data
+    tx_a.send(0x1, &[]).unwrap();
+    tx_b.send(0x2, &[]).unwrap();
+    let mut received = Vec::new();
+    while received.len() < 2 {
+      assert!(cg.next(Duration::seconds(1), |msg, name| received.push((*name, msg.can_id()))).unwrap());
+    }
+    received.sort();
+    assert_eq!(received, [('a', 0x1), ('b', 0x2)]);
+    let (_rx_a, name) = cg.remove(a).unwrap();
+    assert_eq!(name, 'a');
+    assert!(cg.remove(a).is_none());
+    assert_eq!(cg.len(), 1);
+    // frames of removed members are not received anymore
+    tx_a.send(0x1, &[]).unwrap();
+    assert!(!cg.next(Duration::milliseconds(10), |_, _| panic!("removed member received")).unwrap());
+    // handles of removed members are not reused
+    let (tx_c, rx_c) = socket_pair();
+    let c = cg.add(rx_c, 'c').unwrap();
+    assert_ne!(c, a);
+    // the replacement socket takes over the handle and the user data of b
+    let (tx_d, rx_d) = socket_pair();
+    cg.replace(b, rx_d).unwrap();
+    assert_eq!(cg.replace(a, socket_pair().1).err().map(|err| err.kind()), Some(io::ErrorKind::NotFound));
+    tx_b.send(0x2, &[]).unwrap_err();
+    tx_d.send(0x4, &[]).unwrap();
+    tx_c.send(0x3, &[]).unwrap();
+    for (_, _, name) in cg.iter_mut() {
+      *name = name.to_ascii_uppercase();
+    }
+    let mut received = Vec::new();
+    while received.len() < 2 {
+      assert!(cg.next(Duration::seconds(1), |msg, name| received.push((*name, msg.can_id()))).unwrap());
+    }
+    received.sort();
+    assert_eq!(received, [('B', 0x4), ('C', 0x3)]);
+    assert_eq!(cg.get(b).map(|(_, name)| *name), Some('B'));
+  }
+  #[test]
+  fn group_member_errors() {
+    let (tx, rx) = socket_pair();
+    // reading a pipe with recvmsg fails like an unplugged CAN adapter
+    let mut pipe = [0; 2];
+    assert_eq!(unsafe { libc::pipe(pipe.as_mut_ptr()) }, 0);
+    let mut cg = CanGroup::new();
+    let broken = cg.add(Can { fd: pipe[0] }, 'b').unwrap();
+    cg.add(rx, 'r').unwrap();
+    assert_eq!(unsafe { libc::write(pipe[1], [0u8].as_ptr() as *const c_void, 1) }, 1);
+    tx.send(0x7, &[]).unwrap();
+    let mut received = Vec::new();
+    match cg.next(Duration::seconds(1), |msg, name| received.push((*name, msg.can_id()))) {
+      Err(CanGroupError::Members(errors)) => {
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].0, broken);
+        assert_eq!(errors[0].1.raw_os_error(), Some(libc::ENOTSOCK));
+      }
+      result => panic!("unexpected result {:?}", result),
+    }
+    // the frames of the other members are delivered nevertheless
+    assert_eq!(received, [('r', 0x7)]);
+    let (_, name) = cg.remove(broken).unwrap();
+    assert_eq!(name, 'b');
+    let err: io::Error = CanGroupError::Members(vec![(broken, io::Error::from_raw_os_error(libc::ENODEV))]).into();
+    assert_eq!(err.kind(), io::Error::from_raw_os_error(libc::ENODEV).kind());
+    assert!(err.to_string().contains("CanHandle(0)"));
+    let err: io::Error = CanGroupError::Members(Vec::new()).into();
+    assert_eq!(err.kind(), io::ErrorKind::Other);
+    unsafe { libc::close(pipe[1]) };
+  }
+  #[test]
+  fn messages_are_send_and_sync() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Msg>();
+    assert_send_sync::<MsgBatch>();
+  }
+  #[test]
+  fn interface_names() {
+    assert_eq!(ifindex("a_very_long_name").unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    assert_eq!(ifindex("").unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    assert_eq!(ifindex("lo\0").unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    assert!(ifindex("lo").unwrap() > 0);
+  }
+  #[test]
+  fn inverted_filter() {
+    let filter = Filter::inverted(0x123, SFF_MASK);
+    assert_eq!(filter.can_id, 0x123 | INV_FILTER);
+    assert!(filter.is_inverted());
+    assert!(!Filter::new(0x123, SFF_MASK).is_inverted());
+  }
+  #[test]
+  fn msg_flags() {
+    let mut msg = Msg::new();
+    assert!(!msg.is_local() && !msg.is_tx_confirmation());
+    msg.msg.msg_flags = libc::MSG_DONTROUTE | libc::MSG_CONFIRM;
+    assert!(msg.is_local() && msg.is_tx_confirmation());
+  }
+  #[test]
+  fn timestamp_units() {
+    let ts = libc::timespec { tv_sec: 2, tv_nsec: 500 };
+    assert_eq!(timespec_to_duration(&ts), Some(Duration::nanoseconds(2_000_000_500)));
+    let ts = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+    assert_eq!(timespec_to_duration(&ts), None);
+    assert!(Msg::new().timestamp().is_err());
+  }
+  #[test]
+  fn drops_from_cmsg() {
+    let mut msg = Msg::new();
+    assert_eq!(msg.drops(), None);
+    unsafe {
+      let cmsg = libc::CMSG_FIRSTHDR(&msg.msg);
+      (*cmsg).cmsg_level = libc::SOL_SOCKET;
+      (*cmsg).cmsg_type = libc::SO_RXQ_OVFL;
+      (*cmsg).cmsg_len = libc::CMSG_LEN(mem::size_of::<u32>() as u32) as usize;
+      ptr::write_unaligned(libc::CMSG_DATA(cmsg) as *mut u32, 42);
+      msg.msg.msg_controllen = libc::CMSG_SPACE(mem::size_of::<u32>() as u32) as usize;
+    }
+    assert_eq!(msg.drops(), Some(42));
+  }
+  #[test]
+  fn moved_msg() {
+    let (tx, rx) = socket_pair();
+    let mut msg = *Msg::new();
+    tx.send(0x12, &[1, 2]).unwrap();
+    rx.recv(&mut msg).unwrap();
+    let moved = Box::new(msg);
+    assert_eq!(moved.frame(), Some(Frame::Can(CanFrame::new(Id::Standard(0x12), &[1, 2]).unwrap())));
+    assert_eq!(moved.timestamps().hardware, None);
+    assert_eq!(moved.drops(), None);
+  }
+  #[test]
+  fn would_block_and_timed_out() {
+    let (can, _peer) = socket_pair();
+    let mut msg = Msg::new();
+    can.set_nonblocking(true).unwrap();
+    assert!(can.is_nonblocking().unwrap());
+    assert_eq!(can.recv(&mut msg).unwrap_err().kind(), io::ErrorKind::WouldBlock);
+    can.set_nonblocking(false).unwrap();
+    can.set_recv_timeout(Some(Duration::milliseconds(10))).unwrap();
+    assert_eq!(can.recv(&mut msg).unwrap_err().kind(), io::ErrorKind::TimedOut);
+    can.set_recv_timeout(Some(Duration::microseconds(1_500_250))).unwrap();
+    let mut tv: libc::timeval = unsafe { mem::zeroed() };
+    let mut len = mem::size_of::<libc::timeval>() as u32;
+    assert_eq!(unsafe { libc::getsockopt(can.fd, libc::SOL_SOCKET, libc::SO_RCVTIMEO, &mut tv as *mut libc::timeval as *mut c_void, &mut len) }, 0);
+    // the kernel rounds up to whole jiffies
+    assert!(tv.tv_sec == 1 && tv.tv_usec >= 500_250);
+    assert_eq!(can.set_recv_timeout(Some(Duration::milliseconds(-1))).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    can.set_recv_buffer_size(4096).unwrap();
+    assert!(can.recv_buffer_size().unwrap() >= 4096);
+  }
+  #[test]
+  fn batch_round_trip() {
+    let (tx, rx) = socket_pair();
+    let frames = [
+      Frame::Can(CanFrame::new(Id::Standard(0x123), &[1, 2]).unwrap()),
+      Frame::Fd(CanFdFrame::new(Id::Extended(0x12345), &[3; 12]).unwrap().with_brs(true)),
+    ];
+    assert_eq!(tx.send_batch(&frames).unwrap(), 2);
+    let mut batch = MsgBatch::new(4);
+    assert_eq!(rx.recv_batch(&mut batch).unwrap(), 2);
+    let received: Vec<Frame> = batch.iter().map(|msg| msg.frame().unwrap()).collect();
+    assert_eq!(received, frames);
+    assert!(!batch[1].is_empty() && batch[1].is_fd());
+  }
+  #[test]
+  fn xl_round_trip() {
+    let (tx, rx) = socket_pair();
+    let mut msg = Msg::new();
+    // 4 data bytes make the XL frame as long as a classic frame
+    for len in [4, 60, 2048] {
+      let frame = Frame::Xl(CanXlFrame::new(0x100, 1, 0x1234, &vec![0xa5; len]).unwrap());
+      tx.send_frame(&frame).unwrap();
+      rx.recv(&mut msg).unwrap();
+      assert!(msg.is_xl() && !msg.is_fd());
+      assert_eq!(msg.frame(), Some(frame));
+    }
+  }
+  #[test]
+  fn raw_fd_ownership() {
+    use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd};
+    let (tx, rx) = socket_pair();
+    let fd = tx.as_raw_fd();
+    assert_eq!(tx.into_raw_fd(), fd);
+    // the fd is still open after the Can was consumed
+    let tx = unsafe { Can::from_raw_fd(fd) };
+    tx.send(0x123, &[1]).unwrap();
+    let mut msg = Msg::new();
+    rx.recv(&mut msg).unwrap();
+    assert_eq!(msg.can_id(), 0x123);
+  }
+  #[cfg(feature = "mio")]
+  #[test]
+  fn mio_registration() {
+    let (tx, mut rx) = socket_pair();
+    let mut poll = mio::Poll::new().unwrap();
+    poll.registry().register(&mut rx, mio::Token(7), mio::Interest::READABLE).unwrap();
+    tx.send(0x123, &[1]).unwrap();
+    let mut events = mio::Events::with_capacity(4);
+    poll.poll(&mut events, Some(std::time::Duration::from_secs(1))).unwrap();
+    assert!(events.iter().any(|event| event.token() == mio::Token(7) && event.is_readable()));
+    poll.registry().deregister(&mut rx).unwrap();
+  }
+  #[test]
+  fn send_rejects_oversized_payload() {
+    let can = Can { fd: -1 };
+    assert_eq!(can.send(0x123, &[0; 9]).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    assert_eq!(can.send_fd(0x123, 0, &[0; 65]).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+  }
 }
 
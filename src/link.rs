@@ -0,0 +1,626 @@
+//! Configuration and status of CAN interfaces via rtnetlink (what `ip link set can0 type can ...`
+//! and `ip -details -statistics link show can0` do).
+//!
+//! Changing the settings and creating or deleting interfaces requires `CAP_NET_ADMIN`. Bit timing and control modes can only be
+//! changed while the interface is down.
+
+use std::fs::File;
+use std::io;
+use std::mem;
+use std::os::raw::c_int;
+use std::os::unix::io::AsRawFd;
+
+use crate::netlink::{self, NlMsg, Netlink, NLM_F_CREATE, NLM_F_DUMP, NLM_F_EXCL};
+
+// Constants stolen from linux/rtnetlink.h, linux/if_link.h and linux/can/netlink.h
+const RTM_NEWLINK: u16 = 16;
+const RTM_DELLINK: u16 = 17;
+const RTM_GETLINK: u16 = 18;
+const IFLA_IFNAME: u16 = 3;
+const IFLA_MTU: u16 = 4;
+const IFLA_OPERSTATE: u16 = 16;
+const IFLA_LINKINFO: u16 = 18;
+const IFLA_STATS64: u16 = 23;
+const IFLA_NET_NS_FD: u16 = 28;
+const IFLA_INFO_KIND: u16 = 1;
+const IFLA_INFO_DATA: u16 = 2;
+const IFLA_INFO_XSTATS: u16 = 4;
+const IFLA_CAN_BITTIMING: u16 = 1;
+const IFLA_CAN_STATE: u16 = 4;
+const IFLA_CAN_CTRLMODE: u16 = 5;
+const IFLA_CAN_RESTART_MS: u16 = 6;
+const IFLA_CAN_RESTART: u16 = 7;
+const IFLA_CAN_BERR_COUNTER: u16 = 8;
+const IFLA_CAN_DATA_BITTIMING: u16 = 9;
+const VXCAN_INFO_PEER: u16 = 1;
+
+/// loopback mode
+pub const CTRLMODE_LOOPBACK: u32 = 0x01;
+/// listen-only mode
+pub const CTRLMODE_LISTENONLY: u32 = 0x02;
+/// triple sampling mode
+pub const CTRLMODE_3_SAMPLES: u32 = 0x04;
+/// one-shot mode, frames are not retransmitted
+pub const CTRLMODE_ONE_SHOT: u32 = 0x08;
+/// bus-error reporting
+pub const CTRLMODE_BERR_REPORTING: u32 = 0x10;
+/// CAN FD mode
+pub const CTRLMODE_FD: u32 = 0x20;
+/// ignore missing CAN ACKs
+pub const CTRLMODE_PRESUME_ACK: u32 = 0x40;
+/// CAN FD in non-ISO mode
+pub const CTRLMODE_FD_NON_ISO: u32 = 0x80;
+
+/// `struct ifinfomsg`
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub(crate) struct IfInfoMsg {
+  pub family: u8,
+  pub pad: u8,
+  pub ty: u16,
+  pub index: i32,
+  pub flags: u32,
+  pub change: u32,
+}
+
+/// `struct can_bittiming`, only bitrate and sample point are set, the kernel calculates the rest.
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct CanBittiming {
+  bitrate: u32,
+  sample_point: u32,
+  tq: u32,
+  prop_seg: u32,
+  phase_seg1: u32,
+  phase_seg2: u32,
+  sjw: u32,
+  brp: u32,
+}
+
+/// `struct can_ctrlmode`
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct CanCtrlmode {
+  mask: u32,
+  flags: u32,
+}
+
+/// `struct can_berr_counter`
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct CanBerrCounter {
+  txerr: u16,
+  rxerr: u16,
+}
+
+/// `struct can_device_stats`
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct CanDeviceStatsRaw {
+  bus_error: u32,
+  error_warning: u32,
+  error_passive: u32,
+  bus_off: u32,
+  arbitration_lost: u32,
+  restarts: u32,
+}
+
+/// First fields of `struct rtnl_link_stats64`
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct RtnlLinkStats64 {
+  rx_packets: u64,
+  tx_packets: u64,
+  rx_bytes: u64,
+  tx_bytes: u64,
+  rx_errors: u64,
+  tx_errors: u64,
+  rx_dropped: u64,
+  tx_dropped: u64,
+}
+
+/// State of a CAN controller (`enum can_state`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CanState {
+  /// RX/TX error count < 96
+  ErrorActive,
+  /// RX/TX error count < 128
+  ErrorWarning,
+  /// RX/TX error count < 256
+  ErrorPassive,
+  /// RX/TX error count >= 256
+  BusOff,
+  /// device is stopped
+  Stopped,
+  /// device is sleeping
+  Sleeping,
+  /// state unknown to this crate
+  Other(u32),
+}
+impl CanState {
+  fn decode(state: u32) -> CanState {
+    match state {
+      0 => CanState::ErrorActive,
+      1 => CanState::ErrorWarning,
+      2 => CanState::ErrorPassive,
+      3 => CanState::BusOff,
+      4 => CanState::Stopped,
+      5 => CanState::Sleeping,
+      other => CanState::Other(other),
+    }
+  }
+}
+
+/// Bus error counters of a CAN controller.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BerrCounter {
+  /// transmit error counter
+  pub tx: u16,
+  /// receive error counter
+  pub rx: u16,
+}
+
+/// Statistics of a CAN controller.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CanDeviceStats {
+  /// bus errors
+  pub bus_error: u32,
+  /// changes to error warning state
+  pub error_warning: u32,
+  /// changes to error passive state
+  pub error_passive: u32,
+  /// changes to bus off state
+  pub bus_off: u32,
+  /// arbitration lost errors
+  pub arbitration_lost: u32,
+  /// CAN controller re-starts
+  pub restarts: u32,
+}
+
+/// Generic statistics of a network interface.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LinkStats {
+  /// received packets
+  pub rx_packets: u64,
+  /// transmitted packets
+  pub tx_packets: u64,
+  /// received bytes
+  pub rx_bytes: u64,
+  /// transmitted bytes
+  pub tx_bytes: u64,
+  /// bad packets received
+  pub rx_errors: u64,
+  /// packet transmit problems
+  pub tx_errors: u64,
+  /// packets dropped on receive, e.g. because no buffer space was left
+  pub rx_dropped: u64,
+  /// packets dropped on transmit
+  pub tx_dropped: u64,
+}
+
+/// Status of a CAN interface, see `CanInterface::status`. The CAN specific fields are
+/// None if the driver does not report them, e.g. for vcan.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CanStatus {
+  /// state of the controller
+  pub state: Option<CanState>,
+  /// bus error counters
+  pub berr_counter: Option<BerrCounter>,
+  /// statistics of the controller
+  pub device_stats: Option<CanDeviceStats>,
+  /// generic statistics of the interface
+  pub link_stats: Option<LinkStats>,
+}
+impl CanStatus {
+  fn decode(attrs: &[u8]) -> CanStatus {
+    let mut status = CanStatus::default();
+    for (ty, data) in netlink::attrs(attrs) {
+      match ty {
+        IFLA_STATS64 => {
+          let stats: RtnlLinkStats64 = netlink::from_bytes(data);
+          status.link_stats = Some(LinkStats {
+            rx_packets: stats.rx_packets,
+            tx_packets: stats.tx_packets,
+            rx_bytes:   stats.rx_bytes,
+            tx_bytes:   stats.tx_bytes,
+            rx_errors:  stats.rx_errors,
+            tx_errors:  stats.tx_errors,
+            rx_dropped: stats.rx_dropped,
+            tx_dropped: stats.tx_dropped,
+          });
+        }
+        IFLA_LINKINFO => {
+          for (ty, data) in netlink::attrs(data) {
+            match ty {
+              IFLA_INFO_DATA => status.decode_info_data(data),
+              IFLA_INFO_XSTATS => {
+                let stats: CanDeviceStatsRaw = netlink::from_bytes(data);
+                status.device_stats = Some(CanDeviceStats {
+                  bus_error:        stats.bus_error,
+                  error_warning:    stats.error_warning,
+                  error_passive:    stats.error_passive,
+                  bus_off:          stats.bus_off,
+                  arbitration_lost: stats.arbitration_lost,
+                  restarts:         stats.restarts,
+                });
+              }
+              _ => {
+              }
+            }
+          }
+        }
+        _ => {
+        }
+      }
+    }
+    status
+  }
+  fn decode_info_data(&mut self, attrs: &[u8]) {
+    for (ty, data) in netlink::attrs(attrs) {
+      match ty {
+        IFLA_CAN_STATE => self.state = netlink::attr_u32(data).map(CanState::decode),
+        IFLA_CAN_BERR_COUNTER => {
+          let berr: CanBerrCounter = netlink::from_bytes(data);
+          self.berr_counter = Some(BerrCounter { tx: berr.txerr, rx: berr.rxerr });
+        }
+        _ => {
+        }
+      }
+    }
+  }
+}
+
+/// Operational state of a network interface (RFC 2863).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OperState {
+  /// state unknown, e.g. for virtual interfaces which do not report it
+  Unknown,
+  /// some component is missing
+  NotPresent,
+  /// interface is down
+  Down,
+  /// an interface the interface depends on is down
+  LowerLayerDown,
+  /// interface is in test mode
+  Testing,
+  /// interface is waiting for an external event
+  Dormant,
+  /// interface is up
+  Up,
+  /// state unknown to this crate
+  Other(u8),
+}
+impl OperState {
+  fn decode(state: u8) -> OperState {
+    match state {
+      0 => OperState::Unknown,
+      1 => OperState::NotPresent,
+      2 => OperState::Down,
+      3 => OperState::LowerLayerDown,
+      4 => OperState::Testing,
+      5 => OperState::Dormant,
+      6 => OperState::Up,
+      other => OperState::Other(other),
+    }
+  }
+}
+
+/// CAN interface found by `CanInterface::list`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CanInterfaceInfo {
+  /// interface index
+  pub ifindex: i32,
+  /// netdev name, as accepted by `Can::open`
+  pub name: String,
+  /// MTU, tells which frames the interface can carry (see `CanInterfaceInfo::supports_fd`)
+  pub mtu: u32,
+  /// administrative state, true if the interface was set up
+  pub is_up: bool,
+  /// operational state
+  pub oper_state: OperState,
+  /// driver kind (`can`, `vcan`, `vxcan`, ...), None for drivers without netlink support like slcan
+  pub kind: Option<String>,
+}
+impl CanInterfaceInfo {
+  /// Returns true if the interface can carry CAN FD frames.
+  pub fn supports_fd(&self) -> bool {
+    self.mtu as usize >= libc::CANFD_MTU
+  }
+  /// Returns true if the interface can carry CAN XL frames.
+  pub fn supports_xl(&self) -> bool {
+    self.mtu as usize > libc::CANFD_MTU
+  }
+  fn decode(reply: &[u8]) -> Option<CanInterfaceInfo> {
+    let hdr: IfInfoMsg = netlink::from_bytes(reply);
+    if hdr.ty != libc::ARPHRD_CAN {
+      return None;
+    }
+    let mut info = CanInterfaceInfo {
+      ifindex: hdr.index,
+      name: String::new(),
+      mtu: 0,
+      is_up: hdr.flags & libc::IFF_UP as u32 != 0,
+      oper_state: OperState::Unknown,
+      kind: None,
+    };
+    for (ty, data) in netlink::attrs(reply.get(mem::size_of::<IfInfoMsg>()..)?) {
+      match ty {
+        IFLA_IFNAME => info.name = netlink::attr_str(data),
+        IFLA_MTU => info.mtu = netlink::attr_u32(data)?,
+        IFLA_OPERSTATE => info.oper_state = OperState::decode(*data.first()?),
+        IFLA_LINKINFO => {
+          info.kind = netlink::attrs(data).find(|(ty, _)| *ty == IFLA_INFO_KIND).map(|(_, kind)| netlink::attr_str(kind));
+        }
+        _ => {
+        }
+      }
+    }
+    Some(info)
+  }
+}
+
+/// Settings of a CAN controller, see `CanInterface::configure`. Settings which are None
+/// or not in `ctrlmode_mask` are left unchanged.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CanConfig {
+  /// nominal bitrate in bit/s
+  pub bitrate: Option<u32>,
+  /// sample point of the nominal bitrate in tenths of a percent (875 is 87.5 %),
+  /// None lets the kernel choose the sample point
+  pub sample_point: Option<u32>,
+  /// data bitrate of CAN FD in bit/s, requires `CTRLMODE_FD`
+  pub data_bitrate: Option<u32>,
+  /// sample point of the data bitrate in tenths of a percent
+  pub data_sample_point: Option<u32>,
+  /// delay in ms for automatic restarts after bus-off, zero disables them
+  pub restart_ms: Option<u32>,
+  /// control modes to change (`CTRLMODE_LOOPBACK`, `CTRLMODE_FD`, ...)
+  pub ctrlmode_mask: u32,
+  /// new values of the control modes in `ctrlmode_mask`
+  pub ctrlmode_flags: u32,
+}
+impl CanConfig {
+  fn encode(&self, ifindex: c_int) -> NlMsg {
+    let mut msg = NlMsg::new(RTM_NEWLINK, 0);
+    msg.header(&IfInfoMsg { index: ifindex, ..Default::default() });
+    msg.begin_nested(IFLA_LINKINFO).attr_str(IFLA_INFO_KIND, "can").begin_nested(IFLA_INFO_DATA);
+    if let Some(bitrate) = self.bitrate {
+      let bt = CanBittiming { bitrate, sample_point: self.sample_point.unwrap_or(0), ..Default::default() };
+      msg.attr(IFLA_CAN_BITTIMING, crate::netlink::as_bytes(&bt));
+    }
+    if self.ctrlmode_mask != 0 {
+      let cm = CanCtrlmode { mask: self.ctrlmode_mask, flags: self.ctrlmode_flags & self.ctrlmode_mask };
+      msg.attr(IFLA_CAN_CTRLMODE, crate::netlink::as_bytes(&cm));
+    }
+    if let Some(bitrate) = self.data_bitrate {
+      let bt = CanBittiming { bitrate, sample_point: self.data_sample_point.unwrap_or(0), ..Default::default() };
+      msg.attr(IFLA_CAN_DATA_BITTIMING, crate::netlink::as_bytes(&bt));
+    }
+    if let Some(restart_ms) = self.restart_ms {
+      msg.attr_u32(IFLA_CAN_RESTART_MS, restart_ms);
+    }
+    msg.end_nested().end_nested();
+    msg
+  }
+}
+
+/// Network interface of a CAN device.
+pub struct CanInterface {
+  ifindex: c_int,
+}
+impl CanInterface {
+  /// Looks up the interface with the netdev name.
+  pub fn open(ifname: &str) -> io::Result<CanInterface> {
+    Ok(CanInterface { ifindex: crate::ifindex(ifname)? })
+  }
+  /// Lists all CAN interfaces (can, vcan, vxcan, slcan, ...).
+  pub fn list() -> io::Result<Vec<CanInterfaceInfo>> {
+    let mut msg = NlMsg::new(RTM_GETLINK, NLM_F_DUMP);
+    msg.header(&IfInfoMsg::default());
+    Ok(Netlink::open()?.request(&mut msg)?.iter().filter_map(|reply| CanInterfaceInfo::decode(reply)).collect())
+  }
+  /// Creates a virtual CAN interface (`vcan`) with the name. The interface is down.
+  pub fn create_vcan(ifname: &str) -> io::Result<CanInterface> {
+    Netlink::open()?.request(&mut encode_create(ifname, "vcan", None)?)?;
+    CanInterface::open(ifname)
+  }
+  /// Creates a pair of virtual CAN tunnel interfaces (`vxcan`), frames sent on one end are
+  /// received on the other. If `peer_netns` is given (e.g. an opened `/var/run/netns/<name>`),
+  /// the peer is moved into this network namespace. Both interfaces are down.
+  /// Returns the interface with the name `ifname`.
+  pub fn create_vxcan(ifname: &str, peer: &str, peer_netns: Option<&File>) -> io::Result<CanInterface> {
+    let netns = peer_netns.map(|file| file.as_raw_fd());
+    Netlink::open()?.request(&mut encode_create(ifname, "vxcan", Some((peer, netns)))?)?;
+    CanInterface::open(ifname)
+  }
+  /// Deletes the interface. Deleting one end of a vxcan pair also deletes the other one.
+  pub fn delete(self) -> io::Result<()> {
+    let mut msg = NlMsg::new(RTM_DELLINK, 0);
+    msg.header(&IfInfoMsg { index: self.ifindex, ..Default::default() });
+    Netlink::open()?.request(&mut msg)?;
+    Ok(())
+  }
+  /// Get the interface index.
+  pub fn ifindex(&self) -> i32 {
+    self.ifindex
+  }
+  /// Sets the interface up.
+  pub fn set_up(&self) -> io::Result<()> {
+    self.set_flags(libc::IFF_UP as u32, libc::IFF_UP as u32)
+  }
+  /// Sets the interface down.
+  pub fn set_down(&self) -> io::Result<()> {
+    self.set_flags(0, libc::IFF_UP as u32)
+  }
+  /// Applies the settings to the CAN controller in a single request.
+  pub fn configure(&self, config: &CanConfig) -> io::Result<()> {
+    Netlink::open()?.request(&mut config.encode(self.ifindex))?;
+    Ok(())
+  }
+  /// Sets the nominal bitrate in bit/s and lets the kernel choose the sample point.
+  pub fn set_bitrate(&self, bitrate: u32) -> io::Result<()> {
+    self.configure(&CanConfig { bitrate: Some(bitrate), ..Default::default() })
+  }
+  /// Sets the data bitrate in bit/s of CAN FD. The controller must be in `CTRLMODE_FD`.
+  pub fn set_data_bitrate(&self, data_bitrate: u32) -> io::Result<()> {
+    self.configure(&CanConfig { data_bitrate: Some(data_bitrate), ..Default::default() })
+  }
+  /// Sets the delay in ms for automatic restarts after bus-off, zero disables them.
+  pub fn set_restart_ms(&self, restart_ms: u32) -> io::Result<()> {
+    self.configure(&CanConfig { restart_ms: Some(restart_ms), ..Default::default() })
+  }
+  /// Enables or disables the control modes (`CTRLMODE_LISTENONLY`, `CTRLMODE_FD`, ...).
+  pub fn set_ctrlmode(&self, modes: u32, enable: bool) -> io::Result<()> {
+    self.configure(&CanConfig {
+      ctrlmode_mask: modes,
+      ctrlmode_flags: if enable { modes } else { 0 },
+      ..Default::default()
+    })
+  }
+  /// Restarts the controller after bus-off. Fails if the controller is not bus-off.
+  pub fn restart(&self) -> io::Result<()> {
+    let mut msg = NlMsg::new(RTM_NEWLINK, 0);
+    msg.header(&IfInfoMsg { index: self.ifindex, ..Default::default() });
+    msg.begin_nested(IFLA_LINKINFO).attr_str(IFLA_INFO_KIND, "can").begin_nested(IFLA_INFO_DATA)
+       .attr_u32(IFLA_CAN_RESTART, 1)
+       .end_nested().end_nested();
+    Netlink::open()?.request(&mut msg)?;
+    Ok(())
+  }
+  /// Queries the state, error counters and statistics of the interface.
+  pub fn status(&self) -> io::Result<CanStatus> {
+    let mut msg = NlMsg::new(RTM_GETLINK, 0);
+    msg.header(&IfInfoMsg { index: self.ifindex, ..Default::default() });
+    let replies = Netlink::open()?.request(&mut msg)?;
+    let reply = replies.first().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "no reply to RTM_GETLINK"))?;
+    Ok(CanStatus::decode(reply.get(mem::size_of::<IfInfoMsg>()..).unwrap_or(&[])))
+  }
+  fn set_flags(&self, flags: u32, change: u32) -> io::Result<()> {
+    let mut msg = NlMsg::new(RTM_NEWLINK, 0);
+    msg.header(&IfInfoMsg { index: self.ifindex, flags, change, ..Default::default() });
+    Netlink::open()?.request(&mut msg)?;
+    Ok(())
+  }
+}
+
+fn encode_create(ifname: &str, kind: &str, peer: Option<(&str, Option<c_int>)>) -> io::Result<NlMsg> {
+  crate::check_ifname(ifname)?;
+  let mut msg = NlMsg::new(RTM_NEWLINK, NLM_F_CREATE | NLM_F_EXCL);
+  msg.header(&IfInfoMsg::default()).attr_str(IFLA_IFNAME, ifname);
+  msg.begin_nested(IFLA_LINKINFO).attr_str(IFLA_INFO_KIND, kind);
+  if let Some((peer, netns)) = peer {
+    crate::check_ifname(peer)?;
+    msg.begin_nested(IFLA_INFO_DATA).begin_nested(VXCAN_INFO_PEER);
+    msg.header(&IfInfoMsg::default()).attr_str(IFLA_IFNAME, peer);
+    if let Some(fd) = netns {
+      msg.attr_u32(IFLA_NET_NS_FD, fd as u32);
+    }
+    msg.end_nested().end_nested();
+  }
+  msg.end_nested();
+  Ok(msg)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  #[test]
+  fn config_encoding() {
+    let config = CanConfig {
+      bitrate: Some(500_000),
+      sample_point: Some(875),
+      restart_ms: Some(100),
+      ctrlmode_mask: CTRLMODE_LISTENONLY | CTRLMODE_FD,
+      ctrlmode_flags: CTRLMODE_LISTENONLY,
+      ..Default::default()
+    };
+    let bytes = config.encode(3).bytes().to_vec();
+    let u32_at = |i: usize| u32::from_ne_bytes(bytes[i..i + 4].try_into().unwrap());
+    assert_eq!(u32_at(0) as usize, bytes.len());
+    // ifinfomsg
+    assert_eq!(u32_at(20), 3);
+    // IFLA_LINKINFO > IFLA_INFO_KIND "can" > IFLA_INFO_DATA
+    let linkinfo = 16 + mem::size_of::<IfInfoMsg>();
+    assert_eq!(u16::from_ne_bytes([bytes[linkinfo + 2], bytes[linkinfo + 3]]), IFLA_LINKINFO | 0x8000);
+    assert_eq!(&bytes[linkinfo + 8..linkinfo + 12], b"can\0");
+    let data = linkinfo + 12;
+    // IFLA_CAN_BITTIMING with bitrate and sample point
+    let bittiming = data + 4;
+    assert_eq!(u16::from_ne_bytes([bytes[bittiming + 2], bytes[bittiming + 3]]), IFLA_CAN_BITTIMING);
+    assert_eq!((u32_at(bittiming + 4), u32_at(bittiming + 8)), (500_000, 875));
+    // IFLA_CAN_CTRLMODE
+    let ctrlmode = bittiming + 4 + mem::size_of::<CanBittiming>();
+    assert_eq!((u32_at(ctrlmode + 4), u32_at(ctrlmode + 8)), (CTRLMODE_LISTENONLY | CTRLMODE_FD, CTRLMODE_LISTENONLY));
+    // IFLA_CAN_RESTART_MS
+    let restart_ms = ctrlmode + 12;
+    assert_eq!(u32_at(restart_ms + 4), 100);
+    assert_eq!(restart_ms + 8, bytes.len());
+  }
+  #[test]
+  fn status_decoding() {
+    let mut msg = NlMsg::new(RTM_NEWLINK, 0);
+    msg.header(&IfInfoMsg::default());
+    msg.attr(IFLA_STATS64, netlink::as_bytes(&RtnlLinkStats64 { rx_packets: 7, tx_dropped: 2, ..Default::default() }))
+       .begin_nested(IFLA_LINKINFO)
+       .attr_str(IFLA_INFO_KIND, "can")
+       .begin_nested(IFLA_INFO_DATA)
+       .attr_u32(IFLA_CAN_STATE, 2)
+       .attr(IFLA_CAN_BERR_COUNTER, netlink::as_bytes(&CanBerrCounter { txerr: 130, rxerr: 4 }))
+       .end_nested()
+       .attr(IFLA_INFO_XSTATS, netlink::as_bytes(&CanDeviceStatsRaw { bus_off: 1, restarts: 1, ..Default::default() }))
+       .end_nested();
+    let status = CanStatus::decode(&msg.bytes()[16 + mem::size_of::<IfInfoMsg>()..]);
+    assert_eq!(status.state, Some(CanState::ErrorPassive));
+    assert_eq!(status.berr_counter, Some(BerrCounter { tx: 130, rx: 4 }));
+    assert_eq!(status.device_stats.map(|s| (s.bus_off, s.restarts)), Some((1, 1)));
+    assert_eq!(status.link_stats.map(|s| (s.rx_packets, s.tx_dropped)), Some((7, 2)));
+  }
+  #[test]
+  fn info_decoding() {
+    let mut msg = NlMsg::new(RTM_NEWLINK, 0);
+    msg.header(&IfInfoMsg { ty: libc::ARPHRD_CAN, index: 7, flags: libc::IFF_UP as u32, ..Default::default() })
+       .attr_str(IFLA_IFNAME, "vcan0")
+       .attr_u32(IFLA_MTU, libc::CANFD_MTU as u32)
+       .attr(IFLA_OPERSTATE, &[0])
+       .begin_nested(IFLA_LINKINFO).attr_str(IFLA_INFO_KIND, "vcan").end_nested();
+    let info = CanInterfaceInfo::decode(&msg.bytes()[16..]).unwrap();
+    assert_eq!((info.ifindex, info.name.as_str(), info.kind.as_deref()), (7, "vcan0", Some("vcan")));
+    assert!(info.is_up && info.supports_fd() && !info.supports_xl());
+    assert_eq!(info.oper_state, OperState::Unknown);
+    let mut msg = NlMsg::new(RTM_NEWLINK, 0);
+    msg.header(&IfInfoMsg { ty: libc::ARPHRD_LOOPBACK, ..Default::default() });
+    assert_eq!(CanInterfaceInfo::decode(&msg.bytes()[16..]), None);
+    assert!(CanInterface::list().unwrap().iter().all(|info| info.name != "lo"));
+  }
+  #[test]
+  fn status_of_loopback() {
+    let status = CanInterface::open("lo").unwrap().status().unwrap();
+    assert_eq!(status.state, None);
+    assert!(status.link_stats.is_some());
+  }
+  #[test]
+  fn create_encoding() {
+    assert!(encode_create("a_very_long_name", "vcan", None).is_err());
+    let bytes = encode_create("vxcan0", "vxcan", Some(("vxcan1", Some(5)))).unwrap().bytes().to_vec();
+    let attrs: Vec<_> = netlink::attrs(&bytes[16 + mem::size_of::<IfInfoMsg>()..]).collect();
+    assert_eq!(attrs[0], (IFLA_IFNAME, &b"vxcan0\0"[..]));
+    let linkinfo: Vec<_> = netlink::attrs(attrs[1].1).collect();
+    assert_eq!(linkinfo[0], (IFLA_INFO_KIND, &b"vxcan\0"[..]));
+    let data: Vec<_> = netlink::attrs(linkinfo[1].1).collect();
+    assert_eq!(data[0].0, VXCAN_INFO_PEER);
+    let peer: Vec<_> = netlink::attrs(&data[0].1[mem::size_of::<IfInfoMsg>()..]).collect();
+    assert_eq!(peer, vec![(IFLA_IFNAME, &b"vxcan1\0"[..]), (IFLA_NET_NS_FD, &5u32.to_ne_bytes()[..])]);
+  }
+  #[test]
+  #[ignore = "requires the vcan module and CAP_NET_ADMIN"]
+  fn vcan_up_down() {
+    let ifname = format!("sc2link{}", std::process::id() % 100_000);
+    let iface = CanInterface::create_vcan(&ifname).unwrap();
+    iface.set_up().unwrap();
+    iface.set_down().unwrap();
+    // vcan has no bit timing
+    assert!(iface.set_bitrate(500_000).is_err());
+    iface.delete().unwrap();
+    assert!(CanInterface::open(&ifname).is_err());
+  }
+}
@@ -0,0 +1,230 @@
+//! Minimal netlink client used to configure CAN interfaces and gateway rules.
+
+use std::io;
+use std::mem;
+use std::os::raw::{c_int, c_void};
+use std::ptr;
+
+// Constants stolen from linux/netlink.h
+pub(crate) const NLM_F_REQUEST: u16 = 0x0001;
+pub(crate) const NLM_F_ACK: u16 = 0x0004;
+pub(crate) const NLM_F_EXCL: u16 = 0x0200;
+pub(crate) const NLM_F_CREATE: u16 = 0x0400;
+pub(crate) const NLM_F_DUMP: u16 = 0x0300;
+const NLMSG_ERROR: u16 = 2;
+const NLMSG_DONE: u16 = 3;
+const NLA_F_NESTED: u16 = 0x8000;
+const NLA_TYPE_MASK: u16 = 0x3fff;
+
+const NLMSG_HDRLEN: usize = mem::size_of::<libc::nlmsghdr>();
+const NLA_HDRLEN: usize = 4;
+
+fn align(len: usize) -> usize {
+  (len + 3) & !3
+}
+
+/// Returns the raw bytes of a `repr(C)` struct.
+pub(crate) fn as_bytes<T: Copy>(value: &T) -> &[u8] {
+  unsafe { std::slice::from_raw_parts(value as *const T as *const u8, mem::size_of::<T>()) }
+}
+
+/// Reads a `repr(C)` struct from the start of the buffer, missing trailing bytes are zero.
+/// Only use it with plain integer structs.
+pub(crate) fn from_bytes<T: Copy>(buf: &[u8]) -> T {
+  unsafe {
+    let mut value: T = mem::zeroed();
+    ptr::copy_nonoverlapping(buf.as_ptr(), &mut value as *mut T as *mut u8, buf.len().min(mem::size_of::<T>()));
+    value
+  }
+}
+
+/// Builder for a netlink request.
+pub(crate) struct NlMsg {
+  buf: Vec<u8>,
+  nests: Vec<usize>,
+}
+impl NlMsg {
+  /// Starts a request of the type. `NLM_F_REQUEST` and `NLM_F_ACK` are always set.
+  pub fn new(ty: u16, flags: u16) -> NlMsg {
+    let hdr = libc::nlmsghdr {
+      nlmsg_len: 0,
+      nlmsg_type: ty,
+      nlmsg_flags: flags | NLM_F_REQUEST | NLM_F_ACK,
+      nlmsg_seq: 1,
+      nlmsg_pid: 0,
+    };
+    NlMsg { buf: as_bytes(&hdr).to_vec(), nests: Vec::new() }
+  }
+  /// Appends the family specific header, e.g. `struct ifinfomsg`.
+  pub fn header<T: Copy>(&mut self, header: &T) -> &mut NlMsg {
+    self.buf.extend_from_slice(as_bytes(header));
+    self.pad();
+    self
+  }
+  pub fn attr(&mut self, ty: u16, data: &[u8]) -> &mut NlMsg {
+    self.buf.extend_from_slice(&((NLA_HDRLEN + data.len()) as u16).to_ne_bytes());
+    self.buf.extend_from_slice(&ty.to_ne_bytes());
+    self.buf.extend_from_slice(data);
+    self.pad();
+    self
+  }
+  pub fn attr_u32(&mut self, ty: u16, value: u32) -> &mut NlMsg {
+    self.attr(ty, &value.to_ne_bytes())
+  }
+  /// Appends a NUL terminated string.
+  pub fn attr_str(&mut self, ty: u16, value: &str) -> &mut NlMsg {
+    let mut data = value.as_bytes().to_vec();
+    data.push(0);
+    self.attr(ty, &data)
+  }
+  /// Starts a nested attribute, which is closed by `NlMsg::end_nested`.
+  pub fn begin_nested(&mut self, ty: u16) -> &mut NlMsg {
+    self.nests.push(self.buf.len());
+    self.attr(ty | NLA_F_NESTED, &[])
+  }
+  pub fn end_nested(&mut self) -> &mut NlMsg {
+    let start = self.nests.pop().expect("no nested attribute started");
+    let len = (self.buf.len() - start) as u16;
+    self.buf[start..start + 2].copy_from_slice(&len.to_ne_bytes());
+    self
+  }
+  /// Returns the finished request.
+  pub fn bytes(&mut self) -> &[u8] {
+    debug_assert!(self.nests.is_empty());
+    let len = self.buf.len() as u32;
+    self.buf[..4].copy_from_slice(&len.to_ne_bytes());
+    &self.buf
+  }
+  fn pad(&mut self) {
+    self.buf.resize(align(self.buf.len()), 0);
+  }
+}
+
+/// Iterator over the attributes in a buffer, yields the type and the payload.
+pub(crate) struct Attrs<'a> {
+  buf: &'a [u8],
+}
+impl<'a> Iterator for Attrs<'a> {
+  type Item = (u16, &'a [u8]);
+  fn next(&mut self) -> Option<Self::Item> {
+    if self.buf.len() < NLA_HDRLEN {
+      return None;
+    }
+    let len = u16::from_ne_bytes([self.buf[0], self.buf[1]]) as usize;
+    let ty = u16::from_ne_bytes([self.buf[2], self.buf[3]]) & NLA_TYPE_MASK;
+    if len < NLA_HDRLEN || len > self.buf.len() {
+      return None;
+    }
+    let data = &self.buf[NLA_HDRLEN..len];
+    self.buf = &self.buf[align(len).min(self.buf.len())..];
+    Some((ty, data))
+  }
+}
+pub(crate) fn attrs(buf: &[u8]) -> Attrs<'_> {
+  Attrs { buf }
+}
+pub(crate) fn attr_u32(data: &[u8]) -> Option<u32> {
+  Some(u32::from_ne_bytes(data.get(..4)?.try_into().ok()?))
+}
+pub(crate) fn attr_str(data: &[u8]) -> String {
+  let end = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+  String::from_utf8_lossy(&data[..end]).into_owned()
+}
+
+/// Netlink socket of the `NETLINK_ROUTE` family.
+pub(crate) struct Netlink {
+  fd: c_int,
+}
+impl Netlink {
+  pub fn open() -> io::Result<Netlink> {
+    unsafe {
+      let fd = libc::socket(libc::AF_NETLINK, libc::SOCK_RAW | libc::SOCK_CLOEXEC, libc::NETLINK_ROUTE);
+      if fd < 0 {
+        return Err(io::Error::last_os_error());
+      }
+      Ok(Netlink { fd })
+    }
+  }
+  /// Sends the request and collects the payloads (without `struct nlmsghdr`) of all replies
+  /// until the kernel acknowledges the request or finishes the dump.
+  pub fn request(&self, msg: &mut NlMsg) -> io::Result<Vec<Vec<u8>>> {
+    let req = msg.bytes();
+    unsafe {
+      let mut addr: libc::sockaddr_nl = mem::zeroed();
+      addr.nl_family = libc::AF_NETLINK as u16;
+      let nbytes = libc::sendto(self.fd, req.as_ptr() as *const c_void, req.len(), 0,
+                                &addr as *const libc::sockaddr_nl as *const libc::sockaddr, mem::size_of::<libc::sockaddr_nl>() as u32);
+      if nbytes < 0 {
+        return Err(io::Error::last_os_error());
+      }
+    }
+    let mut replies = Vec::new();
+    let mut buf = vec![0u8; 65536];
+    loop {
+      let nbytes = unsafe { libc::recv(self.fd, buf.as_mut_ptr() as *mut c_void, buf.len(), 0) };
+      if nbytes < 0 {
+        return Err(io::Error::last_os_error());
+      }
+      let mut rest = &buf[..nbytes as usize];
+      while rest.len() >= NLMSG_HDRLEN {
+        let hdr: libc::nlmsghdr = from_bytes(rest);
+        let len = hdr.nlmsg_len as usize;
+        if len < NLMSG_HDRLEN || len > rest.len() {
+          return Err(io::Error::new(io::ErrorKind::InvalidData, "truncated netlink message"));
+        }
+        let payload = &rest[NLMSG_HDRLEN..len];
+        match hdr.nlmsg_type {
+          NLMSG_ERROR => {
+            let error = i32::from_ne_bytes(payload.get(..4).and_then(|e| e.try_into().ok()).unwrap_or([0; 4]));
+            if error != 0 {
+              return Err(io::Error::from_raw_os_error(-error));
+            }
+            return Ok(replies);
+          }
+          NLMSG_DONE => return Ok(replies),
+          _ => replies.push(payload.to_vec()),
+        }
+        rest = &rest[align(len).min(rest.len())..];
+      }
+    }
+  }
+}
+impl Drop for Netlink {
+  fn drop(&mut self) {
+    unsafe {
+      libc::close(self.fd);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  #[test]
+  fn nested_attributes() {
+    let mut msg = NlMsg::new(16, 0);
+    msg.header(&[7u8; 3]).begin_nested(18).attr_str(1, "can").attr_u32(2, 5).end_nested();
+    let bytes = msg.bytes().to_vec();
+    assert_eq!(bytes.len(), 16 + 4 + 4 + 8 + 8);
+    assert_eq!(u32::from_ne_bytes(bytes[..4].try_into().unwrap()) as usize, bytes.len());
+    // nested attribute spans the string and the u32 attribute
+    assert_eq!(u16::from_ne_bytes([bytes[20], bytes[21]]), 4 + 8 + 8);
+    assert_eq!(u16::from_ne_bytes([bytes[22], bytes[23]]), 18 | NLA_F_NESTED);
+    assert_eq!(&bytes[28..32], b"can\0");
+    assert_eq!(&bytes[36..40], &5u32.to_ne_bytes());
+    let outer: Vec<_> = attrs(&bytes[20..]).collect();
+    assert_eq!(outer.len(), 1);
+    assert_eq!(outer[0].0, 18);
+    let inner: Vec<_> = attrs(outer[0].1).collect();
+    assert_eq!(inner[0], (1, &b"can\0"[..]));
+    assert_eq!(attr_u32(inner[1].1), Some(5));
+  }
+  #[test]
+  fn string_attributes() {
+    assert_eq!(attr_str(b"can\0"), "can");
+    // the payload is padded with NULs to 4 bytes
+    assert_eq!(attr_str(b"vcan0\0\0\0"), "vcan0");
+    assert_eq!(attr_str(b"can"), "can");
+    assert_eq!(attr_str(b""), "");
+  }
+}
@@ -0,0 +1,88 @@
+//! Owned fd of the protocol sockets (`Bcm`, `IsoTp`, `J1939`).
+
+use std::io;
+use std::mem;
+use std::os::raw::{c_int, c_void};
+
+use crate::{Msg, MAX_PDU, PF_CAN};
+
+/// CAN socket fd, which is closed when dropped.
+pub(crate) struct SocketFd(pub(crate) c_int);
+impl SocketFd {
+  /// Opens a CAN socket of the type and protocol and enables `SO_TIMESTAMP`, like `Can::open` does.
+  pub fn open(ty: c_int, protocol: c_int) -> io::Result<SocketFd> {
+    let fd = unsafe { libc::socket(PF_CAN, ty, protocol) };
+    if fd < 0 {
+      return Err(io::Error::last_os_error());
+    }
+    let socket = SocketFd(fd);
+    socket.set_int(libc::SOL_SOCKET, libc::SO_TIMESTAMP, 1)?;
+    Ok(socket)
+  }
+  pub fn raw(&self) -> c_int {
+    self.0
+  }
+  pub fn set_opt<O>(&self, level: c_int, name: c_int, value: &O) -> io::Result<()> {
+    unsafe {
+      if libc::setsockopt(self.0, level, name, value as *const O as *const c_void, mem::size_of::<O>() as u32) < 0 {
+        return Err(io::Error::last_os_error());
+      }
+    }
+    Ok(())
+  }
+  pub fn set_int(&self, level: c_int, name: c_int, value: c_int) -> io::Result<()> {
+    self.set_opt(level, name, &value)
+  }
+  /// Receives the next message of the protocol into `msg.pdu`. Messages which don't fit into
+  /// the buffer aren't returned truncated, but fail with `InvalidData`.
+  pub fn recv_pdu(&self, msg: &mut Msg, proto: c_int) -> io::Result<()> {
+    unsafe {
+      msg.reset();
+      if msg.pdu.len() < MAX_PDU {
+        msg.pdu.resize(MAX_PDU, 0);
+      }
+      msg.iov[0].iov_base = msg.pdu.as_mut_ptr() as *mut c_void;
+      msg.iov[0].iov_len  = msg.pdu.len();
+      msg.proto           = proto;
+      let nbytes = libc::recvmsg(self.0, &mut msg.msg, 0);
+      if nbytes < 0 {
+        return Err(io::Error::last_os_error());
+      }
+      if msg.msg.msg_flags & libc::MSG_TRUNC != 0 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "message is larger than the receive buffer"));
+      }
+      msg.nbytes = nbytes as usize;
+    }
+    Ok(())
+  }
+}
+impl Drop for SocketFd {
+  fn drop(&mut self) {
+    unsafe {
+      libc::close(self.0);
+    }
+  }
+}
+
+/// Returns a socket fd and its connected peer, see `crate::socket_pair`.
+#[cfg(test)]
+pub(crate) fn socket_pair() -> (SocketFd, std::os::unix::net::UnixDatagram) {
+  use std::os::unix::io::{FromRawFd, IntoRawFd};
+  let (fd, peer) = crate::socket_pair();
+  (SocketFd(fd.into_raw_fd()), unsafe { std::os::unix::net::UnixDatagram::from_raw_fd(peer.into_raw_fd()) })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  #[test]
+  fn oversized_pdu() {
+    let (fd, peer) = socket_pair();
+    let mut msg = Msg::new();
+    peer.send(&vec![0x55; MAX_PDU + 1]).unwrap();
+    assert_eq!(fd.recv_pdu(&mut msg, libc::CAN_ISOTP).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    peer.send(&[0x55; 9]).unwrap();
+    fd.recv_pdu(&mut msg, libc::CAN_J1939).unwrap();
+    assert_eq!(msg.pdu(), Some(&[0x55; 9][..]));
+  }
+}